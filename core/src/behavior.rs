@@ -0,0 +1,109 @@
+//! Built-in movement of powders, liquids and gases, in place of the scripts that make sand fall and water flow.
+
+use rand::Rng;
+
+use crate::grid::Grid;
+
+/// How a cell type moves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Movement {
+    /// falls, and slides down diagonally when it can't
+    Powder,
+    /// falls, slides down diagonally and flows sideways
+    Liquid,
+    /// a liquid that rises, unless its gravity says otherwise
+    Gas,
+}
+
+/// The `behavior` of a cell type with its parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Behavior {
+    pub movement: Movement,
+    /// the direction it falls in, one cell down or up or to a side
+    pub gravity: (i32, i32),
+    /// how many cells a liquid or gas flows sideways in one update
+    pub dispersion: i32,
+}
+
+impl Behavior {
+    /// Reads `behavior`, `gravity` and `dispersion` of a cell type, None if it has no behavior.
+    pub fn from_toml(cell: &toml::Table) -> Result<Option<Self>, String> {
+        let Some(behavior) = cell.get("behavior") else {
+            return Ok(None);
+        };
+        let movement = match behavior.as_str() {
+            Some("powder") => Movement::Powder,
+            Some("liquid") => Movement::Liquid,
+            Some("gas") => Movement::Gas,
+            _ => return Err("behavior is powder, liquid or gas".to_string()),
+        };
+        let gravity = match cell.get("gravity").map(|g| g.as_str()) {
+            None if movement == Movement::Gas => (0, -1),
+            None | Some(Some("down")) => (0, 1),
+            Some(Some("up")) => (0, -1),
+            Some(Some("left")) => (-1, 0),
+            Some(Some("right")) => (1, 0),
+            _ => return Err("gravity is down, up, left or right".to_string()),
+        };
+        let dispersion = match cell.get("dispersion") {
+            Some(dispersion) => dispersion
+                .as_integer()
+                .filter(|&d| d >= 0)
+                .ok_or("dispersion is a number of cells, 0 or more")? as i32,
+            None => 1,
+        };
+        Ok(Some(Behavior {
+            movement,
+            gravity,
+            dispersion,
+        }))
+    }
+}
+
+impl Grid {
+    /// Moves the cell at a position by the behavior of its type, swapping it with a lighter cell.
+    /// Returns where the cell is now, which is where it was if it has no behavior or can't move,
+    /// and None if it left through a void boundary.
+    pub fn apply_behavior(&mut self, (x, y): (i32, i32), rng: &mut impl Rng) -> Option<(i32, i32)> {
+        let state = self.state_at(x, y)?;
+        let me = &self.cell_prescriptors[state as usize];
+        let Some(behavior) = &me.behavior else {
+            return Some((x, y));
+        };
+        let (density, movement, dispersion) = (me.density, behavior.movement, behavior.dispersion);
+        let (gx, gy) = behavior.gravity;
+        // across the direction of gravity, in a random order of its two sides
+        let side = if rng.gen_bool(0.5) { 1 } else { -1 };
+        let sides = [(gy * side, gx * side), (-gy * side, -gx * side)];
+        let lighter = |grid: &Grid, (dx, dy): (i32, i32)| {
+            grid.state_at(x + dx, y + dy)
+                .is_some_and(|other| grid.cell_prescriptors[other as usize].density < density)
+        };
+
+        let mut to = None;
+        if lighter(self, (gx, gy)) {
+            to = Some((gx, gy));
+        }
+        for (sx, sy) in sides {
+            if to.is_none() && lighter(self, (gx + sx, gy + sy)) {
+                to = Some((gx + sx, gy + sy));
+            }
+        }
+        if movement != Movement::Powder {
+            for (sx, sy) in sides {
+                if to.is_some() {
+                    break;
+                }
+                // flow as far as the lighter cells reach, up to the dispersion
+                to = (1..=dispersion)
+                    .take_while(|&k| lighter(self, (sx * k, sy * k)))
+                    .last()
+                    .map(|k| (sx * k, sy * k));
+            }
+        }
+        match to {
+            Some((dx, dy)) if self.swap((x, y), (x + dx, y + dy)) => self.resolve(x + dx, y + dy),
+            _ => Some((x, y)),
+        }
+    }
+}
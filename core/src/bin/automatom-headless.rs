@@ -0,0 +1,23 @@
+//! The headless runner on its own, for machines that can't build raylib.
+//! Takes the same arguments as `automaTom --headless`.
+
+use automatom_core::cli::{self, Args, Command};
+
+fn main() {
+    let args = std::iter::once("--headless".to_string()).chain(std::env::args().skip(1));
+    let args = match Args::parse(args) {
+        Ok(Command::Run(args)) => args,
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(err) = cli::run_headless(&args) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
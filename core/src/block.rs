@@ -0,0 +1,389 @@
+use rand::seq::SliceRandom;
+use rlua::{prelude::*, Variadic};
+
+use crate::grid::{Boundary, Field, Grid, SharedGrid, StateId, OUT_OF_BOUNDS};
+
+/// A 2x2 Margolus block, handed to a cell type's script in place of the Grid.
+/// Positions are relative to the running cell like with the Grid, but
+/// nothing outside of the block can be seen or changed.
+#[derive(Debug, Clone)]
+pub struct Block {
+    /// the grid position of the top left cell
+    pub origin: (i32, i32),
+    /// states in row order, None outside of the grid
+    pub states: [Option<StateId>; 4],
+    /// field values in row order
+    pub data: [Vec<Field>; 4],
+    /// whether a cell is part of the grid and can be written
+    pub writable: [bool; 4],
+    /// the grid, only to look up cell types, it's not locked while the script runs
+    grid: SharedGrid,
+    boundary: Boundary,
+}
+
+impl Block {
+    /// Reads the block at `origin` from the grid.
+    pub fn read(shared: &SharedGrid, origin: (i32, i32)) -> Self {
+        let grid = shared.lock();
+        let mut states = [None; 4];
+        let mut data: [Vec<Field>; 4] = Default::default();
+        let mut writable = [false; 4];
+        let mut claimed = Vec::new();
+        for i in 0..4 {
+            let (x, y) = (origin.0 + i as i32 % 2, origin.1 + i as i32 / 2);
+            states[i] = grid.state_at(x, y);
+            if let Some((_, cell)) = grid.get(x, y) {
+                data[i] = cell.to_vec();
+                // a mirrored position shows a cell that may be in the block already, it is read only,
+                // and no cell of the grid is written from two places of the block
+                let resolved = grid.resolve(x, y);
+                let owned = resolved == Some((x, y)) || grid.boundary == Boundary::Wrap;
+                writable[i] = owned && !claimed.contains(&resolved);
+                if writable[i] {
+                    claimed.push(resolved);
+                }
+            } else if let Some(state) = states[i] {
+                data[i] = grid.cell_prescriptors[state as usize].defaults();
+            }
+        }
+        Block {
+            origin,
+            states,
+            data,
+            writable,
+            grid: shared.clone(),
+            boundary: grid.boundary,
+        }
+    }
+    /// Writes the block back into the grid as a unit.
+    pub fn write(&self, grid: &mut Grid) {
+        for i in 0..4 {
+            if let (true, Some(state)) = (self.writable[i], self.states[i]) {
+                let (x, y) = (self.origin.0 + i as i32 % 2, self.origin.1 + i as i32 / 2);
+                let _ = grid.set_cell(x, y, state, self.data[i].clone());
+            }
+        }
+    }
+    /// Index of a block position, None outside of the block.
+    fn index(x: i32, y: i32) -> Option<usize> {
+        if (0..2).contains(&x) && (0..2).contains(&y) {
+            Some((y * 2 + x) as usize)
+        } else {
+            None
+        }
+    }
+    fn state(&self, x: i32, y: i32) -> Option<StateId> {
+        Block::index(x, y).and_then(|i| self.states[i])
+    }
+    fn set(&mut self, x: i32, y: i32, state: StateId) -> bool {
+        match Block::index(x, y) {
+            Some(i) if self.writable[i] => {
+                let Some(defaults) = self.grid.lock().cell_prescriptors.get(state as usize).map(|cell| cell.defaults())
+                else {
+                    return false;
+                };
+                self.states[i] = Some(state);
+                self.data[i] = defaults;
+                true
+            }
+            _ => false,
+        }
+    }
+    fn swap(&mut self, from: (i32, i32), to: (i32, i32)) -> bool {
+        let (Some(a), Some(b)) = (Block::index(from.0, from.1), Block::index(to.0, to.1)) else {
+            return false;
+        };
+        if !self.writable[a] {
+            return false;
+        }
+        if self.writable[b] {
+            self.states.swap(a, b);
+            self.data.swap(a, b);
+            true
+        } else if self.boundary == Boundary::Void {
+            self.states[a] = Some(0);
+            self.data[a].clear();
+            true
+        } else {
+            false
+        }
+    }
+    /// Turns the contents of the block clockwise by `turns` quarter turns.
+    /// Cells outside of the grid stay where they are, so only full blocks rotate.
+    fn rotate(&mut self, turns: i32) -> bool {
+        if self.writable.contains(&false) {
+            return false;
+        }
+        for _ in 0..turns.rem_euclid(4) {
+            let [a, b, c, d] = self.states;
+            self.states = [c, a, d, b];
+            let [a, b, c, d] = std::mem::take(&mut self.data);
+            self.data = [c, a, d, b];
+        }
+        true
+    }
+    fn copy(&mut self, from: (i32, i32), to: (i32, i32)) -> bool {
+        let (Some(a), Some(b)) = (Block::index(from.0, from.1), Block::index(to.0, to.1)) else {
+            return false;
+        };
+        if !self.writable[b] || self.states[a].is_none() {
+            return false;
+        }
+        self.states[b] = self.states[a];
+        self.data[b] = self.data[a].clone();
+        true
+    }
+    fn field(&self, x: i32, y: i32, name: &str) -> Option<Field> {
+        let i = Block::index(x, y)?;
+        let field = self.grid.lock().cell_prescriptors[self.states[i]? as usize].field_index(name)?;
+        self.data[i].get(field).copied()
+    }
+    fn set_field(&mut self, x: i32, y: i32, name: &str, value: LuaValue) -> LuaResult<bool> {
+        let Some(i) = Block::index(x, y).filter(|&i| self.writable[i]) else {
+            return Ok(false);
+        };
+        let Some(state) = self.states[i] else {
+            return Ok(false);
+        };
+        let grid = self.grid.lock();
+        let cell = &grid.cell_prescriptors[state as usize];
+        let fields = &cell.fields;
+        let Some(field) = fields.iter().position(|(field, _)| field == name) else {
+            return Err(LuaError::RuntimeError(format!("setField: {} has no field {}", cell.name, name)));
+        };
+        self.data[i][field] = fields[field].1.assign(value)?;
+        Ok(true)
+    }
+    /// Offsets from the running cell to every block cell that has one of the states.
+    fn find(&self, (gx, gy): (i32, i32), states: &[i32]) -> Vec<(i32, i32)> {
+        let mut found = Vec::new();
+        for i in 0..4 {
+            if let Some(state) = self.states[i] {
+                if states.contains(&(state as i32)) {
+                    found.push((i as i32 % 2 - gx, i as i32 / 2 - gy));
+                }
+            }
+        }
+        found
+    }
+}
+
+/// The block position of the running cell.
+fn position(ctx: LuaContext) -> LuaResult<(i32, i32)> {
+    let globals = ctx.globals();
+    Ok((globals.get::<_, i32>("x")?, globals.get::<_, i32>("y")?))
+}
+
+impl LuaUserData for Block {
+    fn add_methods<'lua, T: LuaUserDataMethods<'lua, Self>>(methods: &mut T) {
+        methods.add_method("kernel", |ctx, this, (x, y): (i32, i32)| {
+            let (gx, gy) = position(ctx)?;
+            if let Some(state) = this.state(gx + x, gy + y) {
+                let i = Block::index(gx + x, gy + y).unwrap();
+                let grid = this.grid.lock();
+                grid.cell_prescriptors[state as usize].table(ctx, state, &this.data[i])
+            } else {
+                let table = ctx.create_table()?;
+                table.set("state", OUT_OF_BOUNDS)?;
+                table.set("matter", 0)?;
+                Ok(table)
+            }
+        });
+        methods.add_method_mut("update", |ctx, this, (x, y, state): (i32, i32, StateId)| {
+            let (gx, gy) = position(ctx)?;
+            Ok(this.set(gx + x, gy + y, state))
+        });
+        methods.add_method_mut("set", |_, this, (x, y, state): (i32, i32, StateId)| {
+            Ok(this.set(x, y, state))
+        });
+        methods.add_method("choose", |ctx, this, ()| {
+            // any other cell of the block
+            let (gx, gy) = position(ctx)?;
+            let (x, y) = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                .into_iter()
+                .filter(|&pos| pos != (gx, gy))
+                .collect::<Vec<_>>()
+                .choose(&mut *this.grid.rng())
+                .copied()
+                .unwrap();
+            let table = ctx.create_table()?;
+            table.set("x", x - gx)?;
+            table.set("y", y - gy)?;
+            Ok(table)
+        });
+        methods.add_method_mut("copy", |ctx, this, (x, y): (i32, i32)| {
+            let (gx, gy) = position(ctx)?;
+            Ok(this.copy((gx + x, gy + y), (gx, gy)))
+        });
+        methods.add_method("getField", |ctx, this, (x, y, name): (i32, i32, String)| {
+            let (gx, gy) = position(ctx)?;
+            Ok(this.field(gx + x, gy + y, &name))
+        });
+        methods.add_method_mut("setField", |ctx, this, (x, y, name, value): (i32, i32, String, LuaValue)| {
+            let (gx, gy) = position(ctx)?;
+            this.set_field(gx + x, gy + y, &name, value)
+        });
+        methods.add_method("cellState", |_, this, (x, y): (i32, i32)| {
+            Ok(this.state(x, y).unwrap_or(OUT_OF_BOUNDS))
+        });
+        methods.add_method("cellMatter", |_, this, (x, y): (i32, i32)| {
+            Ok(this.state(x, y).map_or(0, |state| this.grid.lock().cell_prescriptors[state as usize].matter))
+        });
+        methods.add_method_mut("swap", |ctx, this, (x, y): (i32, i32)| {
+            let (gx, gy) = position(ctx)?;
+            Ok(this.swap((gx, gy), (gx + x, gy + y)))
+        });
+        methods.add_method_mut("rotate", |_, this, turns: Option<i32>| {
+            Ok(this.rotate(turns.unwrap_or(1)))
+        });
+        methods.add_method("findAll", |ctx, this, state: i32| {
+            let table = ctx.create_table()?;
+            for (x, y) in this.find(position(ctx)?, &[state]) {
+                let table2 = ctx.create_table()?;
+                table2.set("x", x)?;
+                table2.set("y", y)?;
+                table.set(table.len()? + 1, table2)?;
+            }
+            Ok(table)
+        });
+        methods.add_method("isAround", |ctx, this, state: Variadic<i32>| {
+            Ok(!this.find(position(ctx)?, &state).is_empty())
+        });
+        methods.add_method("count", |ctx, this, state: Variadic<i32>| {
+            Ok(this.find(position(ctx)?, &state).len())
+        });
+    }
+}
+
+impl Grid {
+    /// Updates the 2x2 block at `origin` as a unit.
+    /// The script of one of its cells, picked at random among those that have
+    /// one, runs with `grid` set to the block and `x`, `y` to its block position.
+    /// Paused types are not picked, a script that fails pauses its type and leaves the block as it was.
+    pub fn update_block(lua: &Lua, grid: &SharedGrid, origin: (i32, i32)) {
+        let block = Block::read(grid, origin);
+        let mut order = [0, 1, 2, 3];
+        order.shuffle(&mut *grid.rng());
+        let active: Vec<(usize, StateId)> = {
+            let this = grid.lock();
+            order
+                .into_iter()
+                .filter_map(|i| Some((i, block.states[i].filter(|_| block.writable[i])?)))
+                .filter(|&(_, state)| !this.cell_prescriptors[state as usize].paused)
+                .collect()
+        };
+        let mut picked = None;
+        let result = lua.context(|ctx| {
+            let mut script = None;
+            for (i, state) in active {
+                if let Some(function) = Grid::script(ctx, state)? {
+                    script = Some((i, state, function));
+                    break;
+                }
+            }
+            let Some((i, state, script)) = script else {
+                return Ok(None);
+            };
+            picked = Some((i, state));
+            let globals = ctx.globals();
+            globals.set("x", i as i32 % 2)?;
+            globals.set("y", i as i32 / 2)?;
+            globals.set("grid", block)?;
+            let called = script.call::<_, ()>(());
+            let block = globals.get::<_, Block>("grid");
+            globals.set("grid", grid.clone())?;
+            called?;
+            block.map(Some)
+        });
+        match result {
+            Ok(Some(block)) => block.write(&mut grid.lock()),
+            Ok(None) => {}
+            Err(err) => {
+                if let Some((i, state)) = picked {
+                    let position = (origin.0 + i as i32 % 2, origin.1 + i as i32 / 2);
+                    grid.lock().script_failed(state, position, &err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::scheduler::{Mode, Scheduler};
+
+    fn corner_block(boundary: Boundary) -> Block {
+        let (mut grid, _) = Grid::new(4, 4);
+        grid.boundary = boundary;
+        Block::read(&SharedGrid::new(grid, 0), (-1, -1))
+    }
+
+    #[test]
+    fn mirrored_cells_are_read_only() {
+        assert_eq!(corner_block(Boundary::Mirror).writable, [false, false, false, true]);
+        assert_eq!(corner_block(Boundary::Closed).writable, [false, false, false, true]);
+    }
+
+    #[test]
+    fn wrapped_cells_are_writable() {
+        assert_eq!(corner_block(Boundary::Wrap).writable, [true; 4]);
+    }
+
+    #[test]
+    fn no_cell_is_written_twice() {
+        let (mut grid, _) = Grid::new(1, 2);
+        grid.boundary = Boundary::Wrap;
+        let block = Block::read(&SharedGrid::new(grid, 0), (0, 0));
+        assert_eq!(block.writable, [true, false, true, false]);
+    }
+
+    /// Runs the blocks of one generation, returning how many blocks each cell of the grid is in.
+    fn generation(scheduler: &mut Scheduler, grid: &Grid) -> Vec<u32> {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut seen = vec![0; (grid.width * grid.height) as usize];
+        for _ in 0..grid.width * grid.height + 4 {
+            let ((x, y), last) = scheduler.next(&mut rng);
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                if let Some((cx, cy)) = grid.resolve(x + dx, y + dy) {
+                    seen[(cy * grid.width + cx) as usize] += 1;
+                }
+            }
+            if last {
+                return seen;
+            }
+        }
+        panic!("the generation of a {}x{} grid never ends", grid.width, grid.height);
+    }
+
+    #[test]
+    fn blocks_split_the_grid() {
+        let sizes = [(4, 2), (2, 4), (3, 5), (1, 4)];
+        let boundaries = [Boundary::Wrap, Boundary::Wrap, Boundary::Closed, Boundary::Closed];
+        for ((width, height), boundary) in sizes.into_iter().zip(boundaries) {
+            let (mut grid, _) = Grid::new(width, height);
+            grid.boundary = boundary;
+            let mut scheduler = Scheduler::new(Mode::Margolus, &grid);
+            for phase in 0..2 {
+                let seen = generation(&mut scheduler, &grid);
+                assert!(seen.iter().all(|&n| n == 1), "{}x{} phase {}: {:?}", width, height, phase, seen);
+            }
+        }
+    }
+
+    #[test]
+    fn odd_wrapped_grids_finish() {
+        // the reader refuses these, a snapshot could still bring one
+        for (width, height) in [(1, 4), (4, 1), (3, 3)] {
+            let (mut grid, _) = Grid::new(width, height);
+            grid.boundary = Boundary::Wrap;
+            let mut scheduler = Scheduler::new(Mode::Margolus, &grid);
+            for _ in 0..2 {
+                generation(&mut scheduler, &grid);
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Command line arguments, shared by the window and the headless runner.
+
+use std::{fmt::Write, fs};
+
+use crate::grid::{Grid, SharedGrid};
+use crate::reader::{read_grid, Settings};
+use crate::record::{numbered, Recorder};
+use crate::scheduler::Scheduler;
+
+pub const USAGE: &str = "usage: automaTom <rule file> [--headless] [--seed N] [--size WxH]
+                 [--steps N | --updates N] [--out FILE] [--every N] [--scale N]
+                 [--record FILE] [--record-every N]
+
+  --headless   run without a window, needs --steps or --updates
+  --seed N     seed every random number, for a reproducible run
+  --size WxH   replace the width and height of the rule file
+  --steps N    run N generations, in async mode a generation is width * height updates
+  --updates N  run N single updates, or blocks in margolus mode
+  --out FILE   write the grid when the run is done: a picture if FILE ends in .png,
+               a snapshot for .snapshot and the cell ids as text otherwise
+  --every N    also write it every N steps (or updates) to FILE-000100.txt and so on
+  --scale N    pixels per cell in pictures and recordings, 1 by default
+  --record FILE        record the run as an animated GIF if FILE ends in .gif,
+                       as numbered PNGs otherwise
+  --record-every N     generations between recorded frames, 1 by default";
+
+/// What the command line asks for.
+#[derive(Debug, Clone)]
+pub enum Command {
+    Run(Args),
+    /// `--help`, the caller prints `USAGE`
+    Help,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    /// the rule file
+    pub path: String,
+    /// run without a window
+    pub headless: bool,
+    /// seed and size for the rule file
+    pub settings: Settings,
+    pub steps: Option<u64>,
+    pub updates: Option<u64>,
+    /// where to write the grid after the run
+    pub out: Option<String>,
+    /// how often to write a snapshot during the run
+    pub every: Option<u64>,
+    /// pixels per cell in pictures
+    pub scale: Option<i32>,
+    /// where to record the run to
+    pub record: Option<String>,
+    /// generations between recorded frames
+    pub record_every: Option<u64>,
+}
+
+impl Args {
+    /// Parses the arguments after the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+        let mut parsed = Args::default();
+        let mut path = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Command::Help),
+                "--headless" => parsed.headless = true,
+                "--seed" => parsed.settings.seed = Some(number(&arg, &value()?)?),
+                "--size" => parsed.settings.size = Some(size(&value()?)?),
+                "--steps" => parsed.steps = Some(number(&arg, &value()?)?),
+                "--updates" => parsed.updates = Some(number(&arg, &value()?)?),
+                "--out" => parsed.out = Some(value()?),
+                "--every" => parsed.every = Some(number(&arg, &value()?)?).filter(|&every| every > 0),
+                "--scale" => parsed.scale = Some(number(&arg, &value()?)?.clamp(1, 64) as i32),
+                "--record" => parsed.record = Some(value()?),
+                "--record-every" => parsed.record_every = Some(number(&arg, &value()?)?),
+                flag if flag.starts_with("--") => return Err(format!("unknown flag {}", flag)),
+                _ if path.is_none() => path = Some(arg.clone()),
+                _ => return Err(format!("unexpected argument {}", arg)),
+            }
+        }
+        parsed.path = path.ok_or("no rule file given")?;
+        if parsed.steps.is_some() && parsed.updates.is_some() {
+            return Err("--steps and --updates can't be used together".to_string());
+        }
+        let runs = parsed.steps.is_some() || parsed.updates.is_some();
+        if parsed.headless && !runs {
+            return Err("--headless needs --steps or --updates".to_string());
+        }
+        if !parsed.headless && (runs || parsed.out.is_some() || parsed.every.is_some() || parsed.record.is_some()) {
+            return Err("--steps, --updates, --out, --every and --record only apply to --headless runs".to_string());
+        }
+        if parsed.every.is_some() && parsed.out.is_none() {
+            return Err("--every needs --out".to_string());
+        }
+        if parsed.record_every.is_some() && parsed.record.is_none() {
+            return Err("--record-every needs --record".to_string());
+        }
+        Ok(Command::Run(parsed))
+    }
+}
+
+fn number(flag: &str, value: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("{} expects a number, got {}", flag, value))
+}
+
+/// Parses `WxH`.
+fn size(value: &str) -> Result<(i32, i32), String> {
+    let parsed = value.split_once('x').and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+    match parsed {
+        Some((width, height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("--size expects WIDTHxHEIGHT, got {}", value)),
+    }
+}
+
+/// Runs a rule file without a window for the requested steps or updates, writing snapshots along the way.
+pub fn run_headless(args: &Args) -> Result<(), String> {
+    let (lua, grid, options) = read_grid(&args.path, &args.settings)?;
+    for err in &options.errors {
+        eprintln!("{}", err);
+    }
+    let mut scheduler = Scheduler::new(options.mode, &grid.lock());
+    let (total, generations) = match (args.steps, args.updates) {
+        (Some(steps), _) => (steps, true),
+        (_, Some(updates)) => (updates, false),
+        _ => return Err("nothing to run".to_string()),
+    };
+    let scale = args.scale.unwrap_or(1);
+    let mut recorder = match &args.record {
+        Some(path) => {
+            let mut recorder = Recorder::new(path, &grid.lock(), args.record_every.unwrap_or(1), scale)?;
+            recorder.capture(&grid.lock())?;
+            Some(recorder)
+        }
+        None => None,
+    };
+    let mut done = 0;
+    while done < total {
+        let last = scheduler.step(&lua, &grid);
+        if last {
+            print_script_errors(&grid);
+            if let Some(recorder) = &mut recorder {
+                recorder.generation(scheduler.generation, &grid.lock())?;
+            }
+        }
+        if !generations || last {
+            done += 1;
+            if let (Some(every), Some(out)) = (args.every, &args.out) {
+                if done % every == 0 && done < total {
+                    write_output(&grid, &numbered(out, done), scale)?;
+                }
+            }
+        }
+    }
+    print_script_errors(&grid);
+    if let Some(out) = &args.out {
+        write_output(&grid, out, scale)?;
+    }
+    Ok(())
+}
+
+/// Prints the errors of the update scripts that failed since the last call, their types are paused.
+fn print_script_errors(grid: &SharedGrid) {
+    for err in std::mem::take(&mut grid.lock().script_errors) {
+        eprintln!("{}, {} is paused", err, err.name);
+    }
+}
+
+/// Writes the grid in the format the file name asks for.
+fn write_output(grid: &SharedGrid, path: &str, scale: i32) -> Result<(), String> {
+    if path.ends_with(".png") {
+        grid.lock().to_image(scale).write_png(path)
+    } else if path.ends_with(".snapshot") {
+        grid.save(path)
+    } else {
+        write_grid(&grid.lock(), path)
+    }
+}
+
+/// Writes the states of a grid as text: a header naming every cell type, then a line of ids per row.
+pub fn write_grid(grid: &Grid, path: &str) -> Result<(), String> {
+    let mut out = String::new();
+    writeln!(out, "# automaTom {}x{}", grid.width, grid.height).unwrap();
+    for (i, cell) in grid.cell_prescriptors.iter().enumerate() {
+        writeln!(out, "# {} {}", i, cell.name).unwrap();
+    }
+    for y in 0..grid.height {
+        let row: Vec<String> = grid
+            .iter_region((0, y), (grid.width, 1))
+            .map(|(_, state, _)| state.to_string())
+            .collect();
+        out.push_str(&row.join(" "));
+        out.push('\n');
+    }
+    fs::write(path, out).map_err(|err| format!("Failed to write {}: {}", path, err))
+}
@@ -0,0 +1,1062 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rlua::{prelude::*, StdLib, Variadic};
+
+use crate::behavior::Behavior;
+use crate::neighborhood::Neighborhood;
+use crate::reaction::Reaction;
+use crate::rewrite::Rewrite;
+use crate::rule::Rule;
+
+/// Id of a cell type, its index in `Grid::cell_prescriptors`.
+pub type StateId = u16;
+
+/// The state out of bounds positions read as, `OUT_OF_BOUNDS` in Lua.
+/// It is never a valid cell type.
+pub const OUT_OF_BOUNDS: StateId = StateId::MAX;
+
+/// Name of the registry table holding the compiled update scripts, indexed by state + 1.
+const SCRIPTS: &str = "automatom_scripts";
+
+/// A Grid owned by Rust and Lua together, the `grid` global is one of these handles.
+/// Cloning it clones the handle, not the world.
+#[derive(Debug, Clone)]
+pub struct SharedGrid {
+    grid: Arc<Mutex<Grid>>,
+    /// the one random number generator of a run: the scheduler, `grid:choose` and `math.random` all draw from it
+    rng: Arc<Mutex<ChaCha8Rng>>,
+}
+
+impl SharedGrid {
+    pub fn new(grid: Grid, seed: u64) -> Self {
+        SharedGrid {
+            grid: Arc::new(Mutex::new(grid)),
+            rng: Arc::new(Mutex::new(ChaCha8Rng::seed_from_u64(seed))),
+        }
+    }
+    /// Locks the grid, Lua scripts can't run while the guard is held as their methods lock it too.
+    pub fn lock(&self) -> MutexGuard<'_, Grid> {
+        self.grid.lock().unwrap()
+    }
+    /// Locks the random number generator.
+    pub fn rng(&self) -> MutexGuard<'_, ChaCha8Rng> {
+        self.rng.lock().unwrap()
+    }
+    /// Replaces Lua's `math.random` and `math.randomseed` with ones that use the shared generator.
+    pub fn install_random(&self, lua: &Lua) -> LuaResult<()> {
+        lua.context(|ctx| {
+            let math = ctx.globals().get::<_, LuaTable>("math")?;
+            let shared = self.clone();
+            math.set(
+                "random",
+                ctx.create_function(move |_, (m, n): (Option<i64>, Option<i64>)| {
+                    let mut rng = shared.rng();
+                    let (low, high) = match (m, n) {
+                        (None, _) => return Ok(LuaValue::Number(rng.gen())),
+                        (Some(m), None) => (1, m),
+                        (Some(m), Some(n)) => (m, n),
+                    };
+                    if low > high {
+                        return Err(LuaError::RuntimeError(
+                            "bad argument to 'random' (interval is empty)".to_string(),
+                        ));
+                    }
+                    Ok(LuaValue::Integer(rng.gen_range(low..=high)))
+                })?,
+            )?;
+            let shared = self.clone();
+            math.set(
+                "randomseed",
+                ctx.create_function(move |_, seed: Option<i64>| {
+                    let seed = seed.map_or_else(|| rand::thread_rng().gen(), |seed| seed as u64);
+                    *shared.rng() = ChaCha8Rng::seed_from_u64(seed);
+                    Ok(())
+                })?,
+            )
+        })
+    }
+}
+
+/// A Grid is a collection of Cells.
+#[derive(Debug, Clone)]
+pub struct Grid {
+    pub width: i32,
+    pub height: i32,
+    /// the cells that are shown and read by the scripts
+    front: Layer,
+    /// in sync mode updates are written here and shown with `flip`
+    back: Option<Layer>,
+    pub cell_prescriptors: CellPrescriptors,
+    /// what lies beyond the edges
+    pub boundary: Boundary,
+    /// the `[[reaction]]` list, tried in order
+    pub reactions: Vec<Reaction>,
+    /// errors of update scripts not looked at yet, the types they come from are paused
+    pub script_errors: Vec<ScriptError>,
+}
+
+/// What the cells on the edge of a Grid see when they look outside of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Boundary {
+    /// Nothing: reads give `OUT_OF_BOUNDS` and matter 0, writes and swaps fail.
+    Closed,
+    /// The opposite edge, the grid is a torus.
+    Wrap,
+    /// An endless wall of the given cell type that can't be written or moved into.
+    Wall(StateId),
+    /// Air: anything swapped out of the grid is deleted.
+    Void,
+    /// The grid reflected at its edge.
+    Mirror,
+}
+
+impl LuaUserData for SharedGrid {
+    fn add_methods<'lua, T: LuaUserDataMethods<'lua, Self>>(methods: &mut T) {
+        methods.add_method("kernel", |ctx, grid, (x, y): (i32, i32)| {
+            let this = grid.lock();
+            let globals = ctx.globals();
+            let gx = globals.get::<_, i32>("x")?;
+            let gy = globals.get::<_, i32>("y")?;
+            if let Some((state, data)) = this.get(gx + x, gy + y) {
+                this.cell_prescriptors[state as usize].table(ctx, state, data)
+            } else if let Some(state) = this.state_at(gx + x, gy + y) {
+                let me = &this.cell_prescriptors[state as usize];
+                me.table(ctx, state, &me.defaults())
+            } else {
+                let table = ctx.create_table()?;
+                table.set("state", OUT_OF_BOUNDS)?;
+                table.set("matter", 0)?;
+                Ok(table)
+            }
+        });
+        methods.add_method("update", |ctx, grid, (x, y, state): (i32, i32, StateId)| {
+            let mut this = grid.lock();
+            let globals = ctx.globals();
+            let gx = globals.get::<_, i32>("x")?;
+            let gy = globals.get::<_, i32>("y")?;
+            Ok(this.set(gx + x, gy + y, state).is_ok())
+        });
+        methods.add_method("choose", |ctx, grid, neighborhood: Option<LuaValue>| {
+            let this = grid.lock();
+            let gx = ctx.globals().get::<_, i32>("x")?;
+            let gy = ctx.globals().get::<_, i32>("y")?;
+            let (neighborhood, _) = this.query((gx, gy), neighborhood.into_iter().collect())?;
+            let (x, y) = neighborhood
+                .offsets
+                .choose(&mut *grid.rng())
+                .copied()
+                .unwrap_or((0, 0));
+            let table = ctx.create_table()?;
+            table.set("x", x)?;
+            table.set("y", y)?;
+            Ok(table)
+        });
+        methods.add_method("copy", |ctx, grid, (x, y): (i32, i32)| {
+            let mut this = grid.lock();
+            let globals = ctx.globals();
+            let gx = globals.get::<_, i32>("x")?;
+            let gy = globals.get::<_, i32>("y")?;
+            if let Some((state, data)) = this.get(gx + x, gy + y) {
+                let data = data.to_vec();
+                Ok(this.set_cell(gx, gy, state, data).is_ok())
+            } else {
+                let other = this.state_at(gx + x, gy + y).unwrap_or(0);
+                Ok(this.set(gx, gy, other).is_ok())
+            }
+        });
+        methods.add_method("getField", |ctx, grid, (x, y, name): (i32, i32, String)| {
+            let this = grid.lock();
+            let globals = ctx.globals();
+            let gx = globals.get::<_, i32>("x")?;
+            let gy = globals.get::<_, i32>("y")?;
+            Ok(this.field(gx + x, gy + y, &name))
+        });
+        methods.add_method("setField", |ctx, grid, (x, y, name, value): (i32, i32, String, LuaValue)| {
+            let mut this = grid.lock();
+            let globals = ctx.globals();
+            let gx = globals.get::<_, i32>("x")?;
+            let gy = globals.get::<_, i32>("y")?;
+            this.set_field(gx + x, gy + y, &name, value)
+        });
+        methods.add_method("set", |_, grid, (x, y, state): (i32, i32, StateId)| {
+            let mut this = grid.lock();
+            Ok(this.set(x, y, state).is_ok())
+        });
+        methods.add_method("cellState", |_, grid, (x, y): (i32, i32)| {
+            let this = grid.lock();
+            Ok(this.state_at(x, y).unwrap_or(OUT_OF_BOUNDS))
+        });
+        methods.add_method("cellMatter", |_, grid, (x, y): (i32, i32)| {
+            let this = grid.lock();
+            if let Some(state) = this.state_at(x, y) {
+                Ok(this.cell_prescriptors[state as usize].matter)
+            } else {
+                Ok(0)
+            }
+        });
+        methods.add_method("swap", |ctx, grid, (x, y): (i32, i32)| {
+            let mut this = grid.lock();
+            let globals = ctx.globals();
+            let gx = globals.get::<_, i32>("x")?;
+            let gy = globals.get::<_, i32>("y")?;
+            Ok(this.swap((gx, gy), (gx + x, gy + y)))
+        });
+        methods.add_method("findAll", |ctx, grid, args: Variadic<LuaValue>| {
+            let this = grid.lock();
+            let table = ctx.create_table()?;
+            let gx = ctx.globals().get::<_, i32>("x")?;
+            let gy = ctx.globals().get::<_, i32>("y")?;
+            let (neighborhood, states) = this.query((gx, gy), args)?;
+            for ((i, j), cell) in this.neighbors((gx, gy), &neighborhood) {
+                if states.contains(&(cell as i64)) {
+                    let table2 = ctx.create_table()?;
+                    table2.set("x", i)?;
+                    table2.set("y", j)?;
+                    table.set(table.len()? + 1, table2)?;
+                }
+            }
+            Ok(table)
+        });
+        methods.add_method("isAround", |ctx, grid, args: Variadic<LuaValue>| {
+            let this = grid.lock();
+            let gx = ctx.globals().get::<_, i32>("x")?;
+            let gy = ctx.globals().get::<_, i32>("y")?;
+            let (neighborhood, states) = this.query((gx, gy), args)?;
+            let found = this
+                .neighbors((gx, gy), &neighborhood)
+                .any(|(_, cell)| states.contains(&(cell as i64)));
+            Ok(found)
+        });
+        methods.add_method("count", |ctx, grid, args: Variadic<LuaValue>| {
+            let this = grid.lock();
+            let gx = ctx.globals().get::<_, i32>("x")?;
+            let gy = ctx.globals().get::<_, i32>("y")?;
+            let (neighborhood, states) = this.query((gx, gy), args)?;
+            let count = this
+                .neighbors((gx, gy), &neighborhood)
+                .filter(|(_, cell)| states.contains(&(*cell as i64)))
+                .count();
+            Ok(count)
+        });
+    }
+}
+
+impl Grid {
+    /// An empty grid and the Lua state its scripts run in.
+    pub fn new(width: i32, height: i32) -> (Self, Lua) {
+        let front = Layer::new(width, height);
+        let cell_prescriptors = vec![CellPrescriptor {
+            color: Color::BLACK,
+            update: None,
+            matter: 255,
+            name: "air".to_string(),
+            fields: Vec::new(),
+            neighborhood: Neighborhood::default(),
+            rule: None,
+            rewrites: Vec::new(),
+            behavior: None,
+            density: 0.0,
+            paused: false,
+        }];
+        let lua = Lua::new_with(StdLib::BASE | StdLib::MATH | StdLib::TABLE | StdLib::STRING);
+        (Grid {
+            width,
+            height,
+            front,
+            back: None,
+            cell_prescriptors,
+            boundary: Boundary::Closed,
+            reactions: Vec::new(),
+            script_errors: Vec::new(),
+        }, lua)
+
+    }
+    pub fn clear(&mut self) {
+        self.front = Layer::new(self.width, self.height);
+        if let Some(back) = &mut self.back {
+            back.clone_from(&self.front);
+        }
+    }
+    /// Empties the grid and changes its size, the scheduler has to be made anew.
+    pub fn resize(&mut self, width: i32, height: i32) {
+        self.width = width;
+        self.height = height;
+        self.clear();
+    }
+    /// Makes updates go into a back buffer until `flip` is called.
+    pub fn set_sync(&mut self, sync: bool) {
+        self.back = if sync { Some(self.front.clone()) } else { None };
+    }
+    /// Shows the generation written into the back buffer and starts the next one from it.
+    pub fn flip(&mut self) {
+        if let Some(back) = &mut self.back {
+            std::mem::swap(&mut self.front, back);
+            back.clone_from(&self.front);
+        }
+    }
+    /// The buffer updates are written to, the back buffer in sync mode.
+    fn target(&mut self) -> &mut Layer {
+        self.back.as_mut().unwrap_or(&mut self.front)
+    }
+    /// Index of an in-bounds position in the flat buffers of a Layer.
+    fn index(&self, x: i32, y: i32) -> usize {
+        (y * self.width + x) as usize
+    }
+    /// Writes a cell into the target buffer, the position must be in bounds.
+    fn write(&mut self, x: i32, y: i32, state: StateId, data: Vec<Field>) {
+        let i = self.index(x, y);
+        let target = self.target();
+        target.states.set(i, state);
+        target.set_fields(i, &data);
+    }
+    /// Changes a cell in both buffers, for edits made from outside the simulation.
+    pub fn paint(&mut self, x: i32, y: i32, state: StateId) -> Result<(), CellError> {
+        let data = self.cell_prescriptors.get(state as usize).ok_or(CellError::UnknownState)?.defaults();
+        self.paint_cell(x, y, state, data)
+    }
+    /// The same as `paint` with the field values given.
+    pub fn paint_cell(&mut self, x: i32, y: i32, state: StateId, data: Vec<Field>) -> Result<(), CellError> {
+        self.set_cell(x, y, state, data.clone())?;
+        let (x, y) = self.resolve(x, y).ok_or(CellError::OutOfBounds)?;
+        let i = self.index(x, y);
+        self.front.states.set(i, state);
+        self.front.set_fields(i, &data);
+        Ok(())
+    }
+    /// Maps a position onto the cell it refers to, following the boundary.
+    /// Returns None if the position is outside and the boundary has no cells there.
+    pub fn resolve(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        if 0 <= x && x < self.width && 0 <= y && y < self.height {
+            return Some((x, y));
+        }
+        match self.boundary {
+            Boundary::Wrap => Some((x.rem_euclid(self.width), y.rem_euclid(self.height))),
+            Boundary::Mirror => Some((reflect(x, self.width), reflect(y, self.height))),
+            _ => None,
+        }
+    }
+    /// The state and field values of the cell at a position of the front buffer.
+    /// Positions outside of the grid follow the boundary, None if it has no cells there.
+    pub fn get(&self, x: i32, y: i32) -> Option<(StateId, &[Field])> {
+        let (x, y) = self.resolve(x, y)?;
+        let i = self.index(x, y);
+        let state = self.front.states.get(i);
+        Some((state, self.front.fields(i, self.cell_prescriptors[state as usize].fields.len())))
+    }
+    /// Every cell of a rectangle that lies inside the grid, row by row,
+    /// with its position, state and field values.
+    pub fn iter_region(
+        &self,
+        (x, y): (i32, i32),
+        (width, height): (i32, i32),
+    ) -> impl Iterator<Item = ((i32, i32), StateId, &[Field])> + '_ {
+        let columns = x.max(0)..(x + width).min(self.width);
+        let rows = y.max(0)..(y + height).min(self.height);
+        rows.flat_map(move |y| columns.clone().map(move |x| (x, y))).map(|(x, y)| {
+            let i = self.index(x, y);
+            let state = self.front.states.get(i);
+            ((x, y), state, self.front.fields(i, self.cell_prescriptors[state as usize].fields.len()))
+        })
+    }
+    /// The offset and state of every cell of a neighborhood around a position,
+    /// leaving out offsets where the boundary has no cells.
+    pub fn neighbors<'a>(
+        &'a self,
+        (x, y): (i32, i32),
+        neighborhood: &'a Neighborhood,
+    ) -> impl Iterator<Item = ((i32, i32), StateId)> + 'a {
+        neighborhood
+            .offsets
+            .iter()
+            .filter_map(move |&(i, j)| Some(((i, j), self.state_at(x + i, y + j)?)))
+    }
+    /// The state seen at a position, including the cells a wall or void boundary pretends to have.
+    pub fn state_at(&self, x: i32, y: i32) -> Option<StateId> {
+        if let Some((x, y)) = self.resolve(x, y) {
+            return Some(self.front.states.get(self.index(x, y)));
+        }
+        match self.boundary {
+            Boundary::Wall(state) => Some(state),
+            Boundary::Void => Some(0),
+            _ => None,
+        }
+    }
+    /// Swaps two cells, `from` must be inside the grid.
+    /// Swapping into a void boundary deletes the cell, other boundaries refuse.
+    pub fn swap(&mut self, from: (i32, i32), to: (i32, i32)) -> bool {
+        let Some((fx, fy)) = self.resolve(from.0, from.1) else {
+            return false;
+        };
+        match self.resolve(to.0, to.1) {
+            Some((tx, ty)) => {
+                // read both from the front so a swap in sync mode sees the last generation
+                let (from_state, from) = self.get(fx, fy).unwrap();
+                let (to_state, to) = self.get(tx, ty).unwrap();
+                let (from, to) = (from.to_vec(), to.to_vec());
+                self.write(fx, fy, to_state, to);
+                self.write(tx, ty, from_state, from);
+                true
+            }
+            None if self.boundary == Boundary::Void => {
+                let _ = self.set(fx, fy, 0);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Splits the arguments of a neighborhood query into the neighborhood and the states.
+    /// The neighborhood is an optional first argument and defaults to the one of the cell type at `(x, y)`.
+    fn query(&self, (x, y): (i32, i32), args: Variadic<LuaValue>) -> LuaResult<(Cow<'_, Neighborhood>, Vec<i64>)> {
+        let mut args = args.into_iter().peekable();
+        let neighborhood = match args.peek().map(Neighborhood::from_lua).transpose()?.flatten() {
+            Some(neighborhood) => {
+                args.next();
+                Cow::Owned(neighborhood)
+            }
+            None => match self.state_at(x, y) {
+                Some(state) if (state as usize) < self.cell_prescriptors.len() => {
+                    Cow::Borrowed(&self.cell_prescriptors[state as usize].neighborhood)
+                }
+                _ => Cow::Owned(Neighborhood::default()),
+            },
+        };
+        let states = args
+            .map(|state| match state {
+                LuaValue::Integer(state) => Ok(state),
+                LuaValue::Number(state) => Ok(state as i64),
+                other => Err(LuaError::RuntimeError(format!(
+                    "expected a cell type, got {}",
+                    other.type_name()
+                ))),
+            })
+            .collect::<LuaResult<_>>()?;
+        Ok((neighborhood, states))
+    }
+    /// Runs the `[entry].init` script once.
+    /// Besides the `grid` methods it gets an `update.add({x, y, state})` function
+    /// that sets a cell at an absolute position.
+    pub fn init(lua: &Lua, grid: &SharedGrid, script: &str) -> LuaResult<()> {
+        lua.context(|ctx| {
+            let globals = ctx.globals();
+            let update = ctx.create_table()?;
+            let grid = grid.clone();
+            update.set(
+                "add",
+                ctx.create_function(move |_, cell: LuaTable| {
+                    let x = cell.get::<_, i32>(1)?;
+                    let y = cell.get::<_, i32>(2)?;
+                    let state = match cell.get::<_, Option<i32>>(3)? {
+                        Some(state) => state,
+                        None => {
+                            return Err(LuaError::RuntimeError(format!(
+                                "update.add: missing cell type at ({}, {}), is the name spelled right?",
+                                x, y
+                            )))
+                        }
+                    };
+                    let mut grid = grid.lock();
+                    if state < 0 || state as usize >= grid.cell_prescriptors.len() {
+                        return Err(LuaError::RuntimeError(format!(
+                            "update.add: unknown cell type {} at ({}, {})",
+                            state, x, y
+                        )));
+                    }
+                    Ok(grid.set(x, y, state as StateId).is_ok())
+                })?,
+            )?;
+            globals.set("update", update)?;
+            let res = ctx.load(script).set_name("[entry].init")?.exec();
+            globals.set("update", LuaNil)?;
+            res
+        })
+    }
+    /// Compiles the update script of every cell type once, into functions kept in the Lua registry.
+    /// Returns the errors of scripts that do not compile, those cell types are left without one.
+    pub fn compile(&self, lua: &Lua) -> Vec<String> {
+        lua.context(|ctx| {
+            let mut errors = Vec::new();
+            let scripts = ctx.create_table().unwrap();
+            for (i, cell) in self.cell_prescriptors.iter().enumerate() {
+                let Some(update) = &cell.update else {
+                    continue;
+                };
+                match ctx.load(update).set_name(&cell.name).and_then(|chunk| chunk.into_function()) {
+                    Ok(function) => scripts.set(i + 1, function).unwrap(),
+                    Err(err) => errors.push(format!("Error in {}.update: {}", cell.name, err)),
+                }
+            }
+            ctx.set_named_registry_value(SCRIPTS, scripts).unwrap();
+            errors
+        })
+    }
+    /// The compiled update script of a cell type.
+    pub fn script(ctx: LuaContext, state: StateId) -> LuaResult<Option<LuaFunction>> {
+        let scripts = ctx.named_registry_value::<_, LuaTable>(SCRIPTS)?;
+        scripts.get(state as i64 + 1)
+    }
+    /// Runs the script of the cell at a position, the state is looked up without going through Lua.
+    /// Cells a rule or a rewrite applies to are updated natively instead, as are cells a reaction turns into
+    /// another type. A cell with a behavior moves first and its script runs where it ended up.
+    /// The grid is not locked while the script runs. Cells of paused types are skipped,
+    /// a script that fails pauses its type.
+    pub fn update(lua: &Lua, shared: &SharedGrid, (x, y): (i32, i32)) {
+        let (state, (x, y)) = {
+            let mut grid = shared.lock();
+            if grid.state_at(x, y).is_some_and(|state| grid.cell_prescriptors[state as usize].paused) {
+                return;
+            }
+            let mut rng = shared.rng();
+            if grid.apply_rule((x, y))
+                || grid.apply_rewrites((x, y), &mut *rng)
+                || grid.apply_reactions((x, y), &mut *rng)
+            {
+                return;
+            }
+            // most cells of a rule driven world have no script, skip Lua for them
+            let state = grid.state_at(x, y).filter(|&state| grid.cell_prescriptors[state as usize].update.is_some());
+            let Some(moved) = grid.apply_behavior((x, y), &mut *rng) else {
+                return;
+            };
+            (state, moved)
+        };
+        let Some(state) = state else {
+            return;
+        };
+        let result = lua.context(|ctx| {
+            let Some(script) = Grid::script(ctx, state)? else {
+                return Ok(());
+            };
+            let globals = ctx.globals();
+            globals.set("x", x)?;
+            globals.set("y", y)?;
+            script.call::<_, ()>(())
+        });
+        if let Err(err) = result {
+            shared.lock().script_failed(state, (x, y), &err);
+        }
+    }
+    /// Pauses a cell type whose script failed at a position and keeps the error.
+    pub fn script_failed(&mut self, state: StateId, position: (i32, i32), err: &LuaError) {
+        let cell = &mut self.cell_prescriptors[state as usize];
+        cell.paused = true;
+        let err = ScriptError::new(&cell.name, position, err);
+        self.script_errors.push(err);
+    }
+    /*pub fn _update(&mut self, rng: &mut ThreadRng) {
+        // fill the to_change vector if it is empty
+        // this is done to avoid having to iterate over the entire grid
+        if self.to_change.is_empty() {
+            for x in 0..self.width {
+                for y in 0..self.height {
+                    self.to_change.push((x, y));
+                }
+            }
+        }
+
+        // randomly select a cell that needs to be updated
+        let (x, y) = self
+            .to_change
+            .swap_remove(rng.gen_range(0..self.to_change.len()));
+        let cell = &self.cells[x as usize][y as usize];
+
+        let me = &self.cell_prescriptors[cell.state as usize];
+
+        let mut new_state: Result<u8, LuaError> = Err(LuaError::BindError);
+
+        let mut to_update: Vec<(i32, i32, u8)> = Vec::new();
+
+        // call the update function
+        if let Some(update) = &me.update {
+            // get the kernel
+            let (kernel_width, kernel_height, kernel_x, kernel_y) = (3, 3, 1, 1);
+            self.lua
+                .context(|ctx| {
+                    let globals = ctx.globals();
+
+                    // set my coordinates
+                    globals.set("x", cell.x).unwrap();
+                    globals.set("y", cell.y).unwrap();
+
+                    let update_table = ctx.create_table().unwrap();
+                    globals.set("update", update_table).unwrap();
+
+                    globals.set("state", LuaValue::Nil).unwrap();
+                    globals.set("matter", cell.state).unwrap();
+
+                    // set the kernel
+                    let kernel = globals.get::<_, LuaTable>("kernel").unwrap();
+                    for x in 0..kernel_width {
+                        let row = ctx.create_table().unwrap();
+                        for y in 0..kernel_height {
+                            // if out of bounds, set to 0
+                            let state = if 0 <= x as i32 + cell.x as i32 - kernel_x as i32
+                                && 0 <= y as i32 + cell.y as i32 - kernel_y as i32
+                                && x + cell.x - kernel_x < self.width
+                                && y + cell.y - kernel_y < self.height
+                            {
+                                let c = &self.cells[(x + cell.x - kernel_x) as usize]
+                                    [(y + cell.y - kernel_y) as usize];
+                                (c.state, self.cell_prescriptors[c.state as usize].state)
+                            } else {
+                                (255, 0)
+                            };
+                            let cell_table = ctx.create_table().unwrap();
+                            cell_table.set("state", state.0).unwrap();
+                            cell_table.set("matter", state.1).unwrap();
+                            row.set(y + 1, cell_table).unwrap();
+                        }
+                        kernel.set(x + 1, row).unwrap();
+                    }
+
+                    let res = ctx.load(update).exec();
+
+                    new_state = globals.get::<_, u8>("state");
+
+                    let mut idx = 1;
+                    let update = globals.get::<_, LuaTable>("update").unwrap();
+                    while let Ok(data) = update.get::<_, LuaTable>(idx) {
+                        let (x, y, state): (i32, i32, i32) = (
+                            data.get(1).unwrap(),
+                            data.get(2).unwrap(),
+                            data.get(3).unwrap(),
+                        );
+                        to_update.push((
+                            x - 1 + cell.x - kernel_x,
+                            y - 1 + cell.y - kernel_y,
+                            state as u8,
+                        ));
+                        idx += 1;
+                    }
+
+                    res
+                })
+                .unwrap();
+        }
+        if let Ok(new_state) = new_state {
+            self.change_cell(cell.x, cell.y, new_state).unwrap();
+        }
+
+        // update the cells
+        for (x, y, state) in to_update {
+            self.change_cell(x, y, state);
+        }
+    }*/
+    pub fn add_state(
+        &mut self,
+        color: Color,
+        update: Option<String>,
+        matter_state: u8,
+        name: String,
+        fields: Vec<(String, Field)>,
+        neighborhood: Neighborhood,
+    ) {
+        // todo: add the state to the lua context
+        /*self.lua.context(|ctx| {
+            let globals = ctx.globals();
+            globals.set(name.as_str(), self.cell_prescriptors.len()).unwrap();
+        });*/
+        self.cell_prescriptors.push(CellPrescriptor {
+            color,
+            update,
+            matter: matter_state,
+            name,
+            fields,
+            neighborhood,
+            rule: None,
+            rewrites: Vec::new(),
+            behavior: None,
+            density: 255.0 - matter_state as f64,
+            paused: false,
+        });
+    }
+    /// The id of the cell type with a name.
+    pub fn state_by_name(&self, name: &str) -> Option<StateId> {
+        self.cell_prescriptors.iter().position(|cell| cell.name == name).map(|state| state as StateId)
+    }
+    /// Takes over the cells of a grid of the same size, made from another version of the rule file.
+    /// Types and fields are matched by name, fields that are new or changed their type get their defaults
+    /// and cells of types that are gone become air. Returns the names of the gone types that had cells.
+    pub fn adopt(&mut self, old: &Grid) -> Vec<String> {
+        // the new id of every old type and where each of its fields goes
+        let types: Vec<Option<(StateId, Vec<Option<usize>>)>> = old
+            .cell_prescriptors
+            .iter()
+            .map(|cell| {
+                let state = self.state_by_name(&cell.name)?;
+                let new = &self.cell_prescriptors[state as usize];
+                let places = cell
+                    .fields
+                    .iter()
+                    .map(|(name, value)| {
+                        let i = new.field_index(name)?;
+                        let same = std::mem::discriminant(&new.fields[i].1) == std::mem::discriminant(value);
+                        same.then_some(i)
+                    })
+                    .collect();
+                Some((state, places))
+            })
+            .collect();
+        let mut lost = vec![false; types.len()];
+        for ((x, y), old_state, data) in old.iter_region((0, 0), (old.width, old.height)) {
+            let Some((state, places)) = &types[old_state as usize] else {
+                lost[old_state as usize] = true;
+                let _ = self.paint(x, y, 0);
+                continue;
+            };
+            let mut fields = self.cell_prescriptors[*state as usize].defaults();
+            for (value, place) in data.iter().zip(places) {
+                if let Some(i) = place {
+                    fields[*i] = *value;
+                }
+            }
+            let _ = self.paint_cell(x, y, *state, fields);
+        }
+        old.cell_prescriptors
+            .iter()
+            .zip(lost)
+            .filter(|(_, lost)| *lost)
+            .map(|(cell, _)| cell.name.clone())
+            .collect()
+    }
+    /// Changes the type of a cell, its fields get the defaults of the new type.
+    pub fn set(&mut self, x: i32, y: i32, state: StateId) -> Result<(), CellError> {
+        let (x, y) = self.resolve(x, y).ok_or(CellError::OutOfBounds)?;
+        if state as usize >= self.cell_prescriptors.len() {
+            return Err(CellError::UnknownState);
+        }
+        let data = self.cell_prescriptors[state as usize].defaults();
+        self.write(x, y, state, data);
+        /*let (k_x, k_y, k_width, k_height) = self.cell_prescriptors[state as usize].kernel;
+        self.cells[x as usize][y as usize].kernel_spec = (x-k_x);*/
+        Ok(())
+    }
+    /// Changes a cell along with its field values, which must match the fields of its type.
+    pub fn set_cell(&mut self, x: i32, y: i32, state: StateId, data: Vec<Field>) -> Result<(), CellError> {
+        let (x, y) = self.resolve(x, y).ok_or(CellError::OutOfBounds)?;
+        if state as usize >= self.cell_prescriptors.len() {
+            return Err(CellError::UnknownState);
+        }
+        self.write(x, y, state, data);
+        Ok(())
+    }
+    /// The value of a field of the cell at a position, None if it has no such field.
+    pub fn field(&self, x: i32, y: i32, name: &str) -> Option<Field> {
+        let (state, data) = self.get(x, y)?;
+        let i = self.cell_prescriptors[state as usize].field_index(name)?;
+        data.get(i).copied()
+    }
+    /// Sets a field of the cell at a position, converting the value to the type of the field.
+    /// Returns false if the position is outside of the grid.
+    pub fn set_field(&mut self, x: i32, y: i32, name: &str, value: LuaValue) -> LuaResult<bool> {
+        let Some((x, y)) = self.resolve(x, y) else {
+            return Ok(false);
+        };
+        let cell = self.index(x, y);
+        let state = self.target().states.get(cell);
+        let me = &self.cell_prescriptors[state as usize];
+        let Some(i) = me.field_index(name) else {
+            return Err(LuaError::RuntimeError(format!(
+                "setField: {} has no field {}",
+                me.name, name
+            )));
+        };
+        let value = me.fields[i].1.assign(value)?;
+        self.target().fields_mut(cell)[i] = value;
+        Ok(true)
+    }
+}
+
+/// Why a cell could not be written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellError {
+    /// The position is outside of the grid and the boundary has no cell there.
+    OutOfBounds,
+    /// There is no cell type with that id.
+    UnknownState,
+}
+
+/// A Lua error raised by the update script of a cell type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    /// the cell type the script belongs to
+    pub name: String,
+    /// the updated cell
+    pub position: (i32, i32),
+    /// the line of the script, if Lua tells
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+impl ScriptError {
+    pub fn new(name: &str, position: (i32, i32), err: &LuaError) -> Self {
+        // errors in Rust methods keep the message in their cause and the line in their traceback
+        let (mut cause, mut traceback) = (err, "");
+        while let LuaError::CallbackError { traceback: trace, cause: inner } = cause {
+            if traceback.is_empty() {
+                traceback = trace;
+            }
+            cause = inner;
+        }
+        let text = match cause {
+            LuaError::RuntimeError(text) => text.clone(),
+            cause => cause.to_string(),
+        };
+        // scripts are loaded with the type as their name, Lua writes their lines as [string "sand"]:3:
+        let chunk = format!("[string \"{}\"]:", name);
+        let line_in = |text: &str| {
+            let rest = &text[text.find(&chunk)? + chunk.len()..];
+            rest[..rest.find(':')?].parse().ok()
+        };
+        let first = text.lines().next().unwrap_or_default();
+        let message = match first.strip_prefix(&chunk).and_then(|rest| rest.split_once(": ")) {
+            Some((_, message)) => message,
+            None => first,
+        };
+        ScriptError {
+            name: name.to_string(),
+            position,
+            line: line_in(&text).or_else(|| line_in(traceback)),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (x, y) = self.position;
+        write!(f, "{} at ({}, {})", self.name, x, y)?;
+        if let Some(line) = self.line {
+            write!(f, ", line {}", line)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Folds a position back and forth across `0..len` like a mirror on both sides.
+fn reflect(v: i32, len: i32) -> i32 {
+    let v = v.rem_euclid(2 * len);
+    if v < len {
+        v
+    } else {
+        2 * len - 1 - v
+    }
+}
+
+/// One buffer of a Grid: the state and field values of every cell, row by row.
+#[derive(Debug, Clone)]
+struct Layer {
+    /// kept apart from the field values so they stay compact
+    states: States,
+    /// `stride` slots per cell holding the values of its type's fields, in the order of `CellPrescriptor::fields`
+    fields: Vec<Field>,
+    /// the most fields of any type written to the layer so far, 0 while no cell has fields
+    stride: usize,
+}
+
+impl Layer {
+    /// A layer full of air.
+    fn new(width: i32, height: i32) -> Self {
+        let len = (width * height) as usize;
+        Layer {
+            states: States::Narrow(vec![0; len]),
+            fields: Vec::new(),
+            stride: 0,
+        }
+    }
+    /// The first `len` field slots of a cell.
+    fn fields(&self, i: usize, len: usize) -> &[Field] {
+        &self.fields[i * self.stride..][..len]
+    }
+    fn fields_mut(&mut self, i: usize) -> &mut [Field] {
+        &mut self.fields[i * self.stride..][..self.stride]
+    }
+    /// Stores the field values of a cell, widening the slots of every cell when it has more than fit.
+    fn set_fields(&mut self, i: usize, values: &[Field]) {
+        if values.len() > self.stride {
+            let stride = values.len();
+            let mut fields = vec![Field::Int(0); self.states.len() * stride];
+            if self.stride > 0 {
+                for (new, old) in fields.chunks_mut(stride).zip(self.fields.chunks(self.stride)) {
+                    new[..self.stride].copy_from_slice(old);
+                }
+            }
+            self.fields = fields;
+            self.stride = stride;
+        }
+        self.fields[i * self.stride..][..values.len()].copy_from_slice(values);
+    }
+}
+
+/// The states of a Layer, a byte per cell until a state does not fit in one.
+#[derive(Debug, Clone)]
+enum States {
+    Narrow(Vec<u8>),
+    Wide(Vec<StateId>),
+}
+
+impl States {
+    fn len(&self) -> usize {
+        match self {
+            States::Narrow(states) => states.len(),
+            States::Wide(states) => states.len(),
+        }
+    }
+    fn get(&self, i: usize) -> StateId {
+        match self {
+            States::Narrow(states) => states[i] as StateId,
+            States::Wide(states) => states[i],
+        }
+    }
+    /// Sets a state, switching to wide storage when it is larger than a byte.
+    fn set(&mut self, i: usize, state: StateId) {
+        match self {
+            States::Narrow(states) => {
+                if let Ok(state) = u8::try_from(state) {
+                    states[i] = state;
+                } else {
+                    let mut wide: Vec<StateId> = states.iter().map(|&s| s as StateId).collect();
+                    wide[i] = state;
+                    *self = States::Wide(wide);
+                }
+            }
+            States::Wide(states) => states[i] = state,
+        }
+    }
+}
+
+/// The value of a per-cell field, its type is taken from the default in the rule file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl Field {
+    /// Converts a value from Lua to the type of this field.
+    pub fn assign(&self, value: LuaValue) -> LuaResult<Field> {
+        Ok(match (self, value) {
+            (Field::Int(_), LuaValue::Integer(v)) => Field::Int(v),
+            (Field::Int(_), LuaValue::Number(v)) => Field::Int(v as i64),
+            (Field::Float(_), LuaValue::Integer(v)) => Field::Float(v as f64),
+            (Field::Float(_), LuaValue::Number(v)) => Field::Float(v),
+            (Field::Bool(_), LuaValue::Boolean(v)) => Field::Bool(v),
+            (_, value) => {
+                return Err(LuaError::RuntimeError(format!(
+                    "can not store a {} in a {} field",
+                    value.type_name(),
+                    self.type_name()
+                )))
+            }
+        })
+    }
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Field::Int(_) => "integer",
+            Field::Float(_) => "float",
+            Field::Bool(_) => "boolean",
+        }
+    }
+}
+
+impl<'lua> ToLua<'lua> for Field {
+    fn to_lua(self, ctx: LuaContext<'lua>) -> LuaResult<LuaValue<'lua>> {
+        match self {
+            Field::Int(v) => v.to_lua(ctx),
+            Field::Float(v) => v.to_lua(ctx),
+            Field::Bool(v) => v.to_lua(ctx),
+        }
+    }
+}
+
+/// An RGBA color, front-ends convert it to whatever they draw with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color { r: 0, g: 0, b: 0, a: 255 };
+}
+
+/// A CellPrescriptor is a list of colors and their corresponding kernels for a given cell state.
+type CellPrescriptors = Vec<CellPrescriptor>;
+
+/// A CellPrescriptor is a list of colors and their corresponding kernels for a given cell state.
+#[derive(Debug, Clone)]
+pub struct CellPrescriptor {
+    /// The color of the cell.
+    pub color: Color,
+    /// The Lua function that updates the cell.
+    pub update: Option<String>,
+    /// state of matter (solid, liquid, gas, custom..)
+    pub matter: u8,
+    /// name of the state
+    pub name: String,
+    /// names and default values of the per-cell fields
+    pub fields: Vec<(String, Field)>,
+    /// what `count`, `isAround`, `findAll` and `choose` look at
+    pub neighborhood: Neighborhood,
+    /// a Life-like rule that updates cells of this type in place of a script
+    pub rule: Option<Rule>,
+    /// pattern rewrites tried before the update script, the first that applies wins
+    pub rewrites: Vec<Rewrite>,
+    /// built-in movement, applied before the update script
+    pub behavior: Option<Behavior>,
+    /// what moving cells compare, they only displace lighter ones
+    pub density: f64,
+    /// set when the update script failed, cells of a paused type are not updated
+    pub paused: bool,
+}
+
+impl CellPrescriptor {
+    /// Field values of a fresh cell of this type.
+    pub fn defaults(&self) -> Vec<Field> {
+        self.fields.iter().map(|(_, value)| *value).collect()
+    }
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.fields.iter().position(|(field, _)| field == name)
+    }
+    /// The table `grid:kernel` returns for a cell of this type: state, matter and every field.
+    pub fn table<'lua>(&self, ctx: LuaContext<'lua>, state: StateId, data: &[Field]) -> LuaResult<LuaTable<'lua>> {
+        let table = ctx.create_table()?;
+        table.set("state", state)?;
+        table.set("matter", self.matter)?;
+        for ((name, _), value) in self.fields.iter().zip(data) {
+            table.set(name.as_str(), *value)?;
+        }
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wider_fields_keep_the_other_cells() {
+        let (mut grid, _) = Grid::new(2, 2);
+        let white = Color { r: 255, g: 255, b: 255, a: 255 };
+        let water = vec![("temperature".to_string(), Field::Float(20.0))];
+        let sand = vec![("heat".to_string(), Field::Int(0)), ("wet".to_string(), Field::Bool(false))];
+        grid.add_state(white, None, 1, "water".to_string(), water, Neighborhood::default());
+        grid.add_state(white, None, 1, "sand".to_string(), sand, Neighborhood::default());
+        grid.set_sync(true);
+        grid.paint_cell(1, 0, 1, vec![Field::Float(5.5)]).unwrap();
+        grid.paint_cell(0, 1, 2, vec![Field::Int(3), Field::Bool(true)]).unwrap();
+        grid.flip();
+        assert_eq!(grid.get(0, 0), Some((0, &[][..])));
+        assert_eq!(grid.get(1, 0), Some((1, &[Field::Float(5.5)][..])));
+        assert_eq!(grid.get(0, 1), Some((2, &[Field::Int(3), Field::Bool(true)][..])));
+        assert_eq!(grid.field(1, 0, "temperature"), Some(Field::Float(5.5)));
+    }
+}
@@ -0,0 +1,121 @@
+//! Worlds as images: levels drawn in an image editor and pictures of the grid.
+
+use std::{fs::File, io::BufWriter};
+
+use crate::grid::{Color, Grid, StateId};
+
+/// The pixels of an image, row by row.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<Color>,
+}
+
+impl Image {
+    /// Decodes a PNG of any color type and bit depth.
+    pub fn read_png(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|err| format!("{}: {}", path, err))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(|err| format!("{}: {}", path, err))?;
+        let bytes = &buffer[..info.buffer_size()];
+        let pixels = match info.color_type {
+            png::ColorType::Grayscale => bytes.iter().map(|&v| Color { r: v, g: v, b: v, a: 255 }).collect(),
+            png::ColorType::GrayscaleAlpha => bytes
+                .chunks_exact(2)
+                .map(|p| Color { r: p[0], g: p[0], b: p[0], a: p[1] })
+                .collect(),
+            png::ColorType::Rgb => bytes
+                .chunks_exact(3)
+                .map(|p| Color { r: p[0], g: p[1], b: p[2], a: 255 })
+                .collect(),
+            png::ColorType::Rgba => bytes
+                .chunks_exact(4)
+                .map(|p| Color { r: p[0], g: p[1], b: p[2], a: p[3] })
+                .collect(),
+            // expanded by normalize_to_color8
+            png::ColorType::Indexed => unreachable!(),
+        };
+        Ok(Image {
+            width: info.width as i32,
+            height: info.height as i32,
+            pixels,
+        })
+    }
+    /// Writes the image as an 8 bit RGBA PNG.
+    pub fn write_png(&self, path: &str) -> Result<(), String> {
+        let file = File::create(path).map_err(|err| format!("Failed to write {}: {}", path, err))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let bytes: Vec<u8> = self.pixels.iter().flat_map(|c| [c.r, c.g, c.b, c.a]).collect();
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&bytes))
+            .map_err(|err| format!("Failed to write {}: {}", path, err))
+    }
+}
+
+impl Grid {
+    /// Paints every pixel of an image that lies inside the grid as the cell type of the nearest color.
+    /// The palette lists the colors to pick from, transparent pixels are left as they are.
+    pub fn import_image(&mut self, image: &Image, palette: &[(Color, StateId)]) {
+        for y in 0..image.height.min(self.height) {
+            for x in 0..image.width.min(self.width) {
+                let pixel = image.pixels[(y * image.width + x) as usize];
+                if pixel.a < 128 {
+                    continue;
+                }
+                let nearest = palette.iter().min_by_key(|(color, _)| distance(*color, pixel));
+                if let Some(&(_, state)) = nearest {
+                    let _ = self.paint(x, y, state);
+                }
+            }
+        }
+    }
+    /// The grid as an image, each cell `scale` pixels wide and high in the color of its type.
+    pub fn to_image(&self, scale: i32) -> Image {
+        let (width, height) = (self.width * scale, self.height * scale);
+        let mut pixels = vec![Color::BLACK; (width * height) as usize];
+        for ((x, y), state, _) in self.iter_region((0, 0), (self.width, self.height)) {
+            let color = self.cell_prescriptors[state as usize].color;
+            for py in y * scale..(y + 1) * scale {
+                let row = (py * width) as usize;
+                pixels[row + (x * scale) as usize..row + ((x + 1) * scale) as usize].fill(color);
+            }
+        }
+        Image { width, height, pixels }
+    }
+    /// Every cell type with its color, the palette `import_image` uses without a `[entry.palette]`.
+    pub fn palette(&self) -> Vec<(Color, StateId)> {
+        self.cell_prescriptors
+            .iter()
+            .enumerate()
+            .map(|(state, cell)| (cell.color, state as StateId))
+            .collect()
+    }
+}
+
+/// Squared distance of two colors in RGB space.
+fn distance(a: Color, b: Color) -> i32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+    d(a.r, b.r) + d(a.g, b.g) + d(a.b, b.b)
+}
+
+/// Parses `#rrggbb`.
+pub fn parse_hex(hex: &str) -> Option<Color> {
+    let hex = hex.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some(Color {
+        r: channel(0)?,
+        g: channel(2)?,
+        b: channel(4)?,
+        a: 255,
+    })
+}
@@ -0,0 +1,19 @@
+//! The automaTom engine: grids, cell types, their Lua scripts and the rule file loader.
+//! Nothing in here draws or needs a display, front-ends read the grid and draw it themselves.
+
+pub mod behavior;
+pub mod block;
+pub mod cli;
+pub mod grid;
+pub mod image;
+pub mod neighborhood;
+pub mod pattern;
+pub mod reaction;
+pub mod reader;
+pub mod rewrite;
+pub mod record;
+pub mod rule;
+pub mod scheduler;
+pub mod snapshot;
+
+pub use rlua;
@@ -0,0 +1,122 @@
+use rlua::prelude::*;
+
+/// The cells a cell looks at when it counts or searches its surroundings,
+/// as offsets from its own position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Neighborhood {
+    pub offsets: Vec<(i32, i32)>,
+}
+
+impl Default for Neighborhood {
+    /// The 3x3 square around the cell, including the cell itself.
+    /// Cell types without a `neighborhood` get this one.
+    fn default() -> Self {
+        Neighborhood::moore(1, true)
+    }
+}
+
+impl Neighborhood {
+    /// The square of the given radius.
+    pub fn moore(radius: i32, center: bool) -> Self {
+        Neighborhood::filter(radius, center, |_, _| true)
+    }
+    /// The diamond of the given radius, only orthogonal steps count.
+    pub fn von_neumann(radius: i32, center: bool) -> Self {
+        Neighborhood::filter(radius, center, |x, y| x.abs() + y.abs() <= radius)
+    }
+    /// The hexagon of the given radius, for hexagonal worlds stored in axial
+    /// coordinates: the up-right and down-left diagonals are neighbours, the other two are not.
+    pub fn hexagonal(radius: i32, center: bool) -> Self {
+        Neighborhood::filter(radius, center, |x, y| (x + y).abs() <= radius)
+    }
+    /// Every marked cell of a mask given as rows of text, `1`, `#` or `x` mark a neighbour.
+    /// The middle of the mask is the cell itself, so it needs an odd number of rows and columns.
+    pub fn mask(rows: &[String]) -> Result<Self, String> {
+        let height = rows.len() as i32;
+        let width = rows.first().map_or(0, |row| row.chars().count()) as i32;
+        if height % 2 == 0 || width % 2 == 0 {
+            return Err("a neighborhood mask needs an odd number of rows and columns".to_string());
+        }
+        let mut offsets = Vec::new();
+        for (y, row) in rows.iter().enumerate() {
+            if row.chars().count() as i32 != width {
+                return Err("all rows of a neighborhood mask must have the same length".to_string());
+            }
+            for (x, c) in row.chars().enumerate() {
+                if matches!(c, '1' | '#' | 'x') {
+                    offsets.push((x as i32 - width / 2, y as i32 - height / 2));
+                }
+            }
+        }
+        Ok(Neighborhood { offsets })
+    }
+    fn filter(radius: i32, center: bool, keep: impl Fn(i32, i32) -> bool) -> Self {
+        let mut offsets = Vec::new();
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                if (center || (x, y) != (0, 0)) && keep(x, y) {
+                    offsets.push((x, y));
+                }
+            }
+        }
+        Neighborhood { offsets }
+    }
+    /// Builds a neighborhood from its description, shared by the rule file and Lua.
+    /// A named shape has radius 1 and leaves out the cell itself unless told otherwise.
+    pub fn describe(
+        shape: Option<&str>,
+        radius: Option<i64>,
+        center: Option<bool>,
+        mask: Option<Vec<String>>,
+    ) -> Result<Self, String> {
+        if let Some(mask) = mask {
+            return Neighborhood::mask(&mask);
+        }
+        let radius = radius.unwrap_or(1) as i32;
+        let center = center.unwrap_or(false);
+        match shape.unwrap_or("moore") {
+            "moore" => Ok(Neighborhood::moore(radius, center)),
+            "vonneumann" | "von_neumann" => Ok(Neighborhood::von_neumann(radius, center)),
+            "hex" | "hexagonal" => Ok(Neighborhood::hexagonal(radius, center)),
+            other => Err(format!("unknown neighborhood {}", other)),
+        }
+    }
+    /// Reads `neighborhood = "shape"` or `neighborhood = { shape, radius, center, mask }`.
+    pub fn from_toml(value: &toml::Value) -> Result<Self, String> {
+        if let Some(shape) = value.as_str() {
+            return Neighborhood::describe(Some(shape), None, None, None);
+        }
+        let table = value.as_table().ok_or("a neighborhood is a name or a table")?;
+        let mask = match table.get("mask") {
+            Some(mask) => Some(
+                mask.as_array()
+                    .ok_or("a neighborhood mask is a list of rows")?
+                    .iter()
+                    .map(|row| row.as_str().map(String::from).ok_or("mask rows are strings"))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => None,
+        };
+        Neighborhood::describe(
+            table.get("shape").and_then(|shape| shape.as_str()),
+            table.get("radius").and_then(|radius| radius.as_integer()),
+            table.get("center").and_then(|center| center.as_bool()),
+            mask,
+        )
+    }
+    /// The same as `from_toml` for a neighborhood passed to a `grid` method,
+    /// None if the value does not describe one.
+    pub fn from_lua(value: &LuaValue) -> LuaResult<Option<Self>> {
+        let nh = match value {
+            LuaValue::String(shape) => Neighborhood::describe(Some(shape.to_str()?), None, None, None),
+            LuaValue::Table(table) => Neighborhood::describe(
+                table.get::<_, Option<String>>("shape")?.as_deref(),
+                table.get("radius")?,
+                table.get("center")?,
+                table.get("mask")?,
+            ),
+            _ => return Ok(None),
+        };
+        nh.map(Some).map_err(LuaError::RuntimeError)
+    }
+}
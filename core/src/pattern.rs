@@ -0,0 +1,388 @@
+//! Patterns in the formats Life-like automata are shared in: Golly's RLE and plaintext `.cells`.
+
+use std::fs;
+
+use crate::grid::{Grid, StateId};
+
+/// The widest and tallest pattern that is read, larger ones are refused before anything is allocated.
+const MAX_SIDE: usize = 1 << 14;
+
+fn too_large() -> String {
+    format!("patterns larger than {0}x{0} are not supported", MAX_SIDE)
+}
+
+/// A rectangle of pattern states, 0 is dead, 1 alive and higher ones are the extra states of multi-state rules.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub width: i32,
+    pub height: i32,
+    /// states row by row
+    pub cells: Vec<u8>,
+    /// the `rule` of an RLE header
+    pub rule: Option<String>,
+}
+
+impl Pattern {
+    /// Reads a `.cells` file or, for any other extension, an RLE file.
+    pub fn read(path: &str) -> Result<Self, String> {
+        let text =
+            fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+        let pattern = if path.ends_with(".cells") {
+            Pattern::parse_cells(&text)
+        } else {
+            Pattern::parse_rle(&text)
+        };
+        pattern.map_err(|err| format!("{}: {}", path, err))
+    }
+    /// Writes a `.cells` file or, for any other extension, an RLE file.
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let text = if path.ends_with(".cells") {
+            self.to_cells()
+        } else {
+            self.to_rle()
+        };
+        fs::write(path, text).map_err(|err| format!("Failed to write {}: {}", path, err))
+    }
+    /// Parses RLE: `b`/`.` are dead, `o` alive, `A` to `X` the states 1 to 24 and
+    /// `pA` to `yO` the states above, a number before any of them repeats it.
+    pub fn parse_rle(text: &str) -> Result<Self, String> {
+        let mut rows: Vec<Vec<u8>> = vec![Vec::new()];
+        let (mut width, mut height, mut rule) = (0, 0, None);
+        let mut count = 0usize;
+        let mut prefix = None;
+        'lines: for line in text.lines() {
+            let line = line.trim();
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            if line.starts_with('x') {
+                for pair in line.split(',') {
+                    let (key, value) = pair
+                        .split_once('=')
+                        .ok_or("the header is made of key = value pairs")?;
+                    match key.trim() {
+                        "x" => width = value.trim().parse().map_err(|_| "x is not a number")?,
+                        "y" => height = value.trim().parse().map_err(|_| "y is not a number")?,
+                        "rule" => rule = Some(value.trim().to_string()),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            for c in line.chars() {
+                if prefix.is_some() && !c.is_ascii_uppercase() {
+                    return Err("a state prefix must be followed by A to X".to_string());
+                }
+                let run = count.max(1);
+                let state = match c {
+                    '0'..='9' => {
+                        count = count
+                            .checked_mul(10)
+                            .and_then(|count| count.checked_add(c.to_digit(10).unwrap() as usize))
+                            .filter(|&count| count <= MAX_SIDE)
+                            .ok_or_else(too_large)?;
+                        continue;
+                    }
+                    'p'..='y' if prefix.is_none() => {
+                        prefix = Some(c as u8 - b'p' + 1);
+                        continue;
+                    }
+                    'b' | '.' => 0,
+                    'o' => 1,
+                    'A'..='X' => {
+                        let state = prefix.take().unwrap_or(0) as usize * 24
+                            + (c as u8 - b'A' + 1) as usize;
+                        u8::try_from(state).map_err(|_| "states above 255 are not supported")?
+                    }
+                    '$' => {
+                        if rows.len() + run > MAX_SIDE {
+                            return Err(too_large());
+                        }
+                        for _ in 0..run {
+                            rows.push(Vec::new());
+                        }
+                        count = 0;
+                        continue;
+                    }
+                    '!' => break 'lines,
+                    c if c.is_whitespace() => continue,
+                    other => return Err(format!("unexpected {} in the pattern", other)),
+                };
+                let row = rows.last_mut().unwrap();
+                if row.len() + run > MAX_SIDE {
+                    return Err(too_large());
+                }
+                row.extend(std::iter::repeat_n(state, run));
+                count = 0;
+            }
+        }
+        Pattern::from_rows(rows, width, height, rule)
+    }
+    /// Parses plaintext: `!` starts a comment line, `.` is dead and `O` or `*` alive.
+    pub fn parse_cells(text: &str) -> Result<Self, String> {
+        let mut rows = Vec::new();
+        for line in text.lines() {
+            if line.starts_with('!') {
+                continue;
+            }
+            let row = line
+                .trim_end()
+                .chars()
+                .map(|c| match c {
+                    '.' => Ok(0),
+                    'O' | '*' => Ok(1),
+                    other => Err(format!("unexpected {} in the pattern", other)),
+                })
+                .collect::<Result<Vec<u8>, _>>()?;
+            rows.push(row);
+        }
+        Pattern::from_rows(rows, 0, 0, None)
+    }
+    /// Pads ragged rows with dead cells, the pattern is at least `width` by `height`.
+    fn from_rows(rows: Vec<Vec<u8>>, width: i32, height: i32, rule: Option<String>) -> Result<Self, String> {
+        let width = rows
+            .iter()
+            .map(|row| row.len() as i32)
+            .max()
+            .unwrap_or(0)
+            .max(width);
+        let height = (rows.len() as i32).max(height);
+        if width as usize > MAX_SIDE || height as usize > MAX_SIDE {
+            return Err(too_large());
+        }
+        let mut cells = vec![0; (width * height) as usize];
+        for (y, row) in rows.iter().enumerate() {
+            let start = y * width as usize;
+            cells[start..start + row.len()].copy_from_slice(row);
+        }
+        Ok(Pattern {
+            width,
+            height,
+            cells,
+            rule,
+        })
+    }
+    fn row(&self, y: i32) -> &[u8] {
+        &self.cells[(y * self.width) as usize..((y + 1) * self.width) as usize]
+    }
+    /// The pattern as RLE, in `b`/`o` if it only has two states and in letters otherwise.
+    pub fn to_rle(&self) -> String {
+        let two_states = self.cells.iter().all(|&state| state <= 1);
+        let tag = |state: u8| match (two_states, state) {
+            (true, 0) => "b".to_string(),
+            (true, _) => "o".to_string(),
+            (false, 0) => ".".to_string(),
+            (false, state) if state <= 24 => ((b'A' + state - 1) as char).to_string(),
+            (false, state) => {
+                let (prefix, letter) = ((state - 1) / 24, (state - 1) % 24);
+                format!("{}{}", (b'p' + prefix - 1) as char, (b'A' + letter) as char)
+            }
+        };
+        let mut runs = Vec::new();
+        let mut empty_rows = 0;
+        let mut written = false;
+        for y in 0..self.height {
+            let row = self.row(y);
+            // trailing dead cells are left out
+            let len = row
+                .iter()
+                .rposition(|&state| state != 0)
+                .map_or(0, |last| last + 1);
+            if len == 0 {
+                empty_rows += 1;
+                continue;
+            }
+            // one `$` ends the previous row, the others skip the empty rows
+            if written {
+                runs.push(run(empty_rows + 1, "$"));
+            } else if empty_rows > 0 {
+                runs.push(run(empty_rows, "$"));
+            }
+            empty_rows = 0;
+            written = true;
+            let mut x = 0;
+            while x < len {
+                let state = row[x];
+                let same = row[x..len]
+                    .iter()
+                    .take_while(|&&other| other == state)
+                    .count();
+                runs.push(run(same, &tag(state)));
+                x += same;
+            }
+        }
+        runs.push("!".to_string());
+
+        let mut out = format!("x = {}, y = {}", self.width, self.height);
+        if let Some(rule) = &self.rule {
+            out += &format!(", rule = {}", rule);
+        }
+        out.push('\n');
+        // lines are kept under 70 characters like Golly does
+        let mut line = String::new();
+        for run in runs {
+            if line.len() + run.len() > 70 {
+                out += &line;
+                out.push('\n');
+                line.clear();
+            }
+            line += &run;
+        }
+        out += &line;
+        out.push('\n');
+        out
+    }
+    /// The pattern as plaintext, any state but dead is written alive.
+    pub fn to_cells(&self) -> String {
+        let mut out = String::new();
+        for y in 0..self.height {
+            out.extend(
+                self.row(y)
+                    .iter()
+                    .map(|&state| if state == 0 { '.' } else { 'O' }),
+            );
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn run(count: usize, tag: &str) -> String {
+    if count == 1 {
+        tag.to_string()
+    } else {
+        format!("{}{}", count, tag)
+    }
+}
+
+impl Grid {
+    /// Writes a pattern with its top left corner at `(x, y)`, dead cells included.
+    /// Pattern state `n` becomes the cell type `types[n]`, or the type with id `n` if `types` is shorter.
+    pub fn place_pattern(
+        &mut self,
+        pattern: &Pattern,
+        (x, y): (i32, i32),
+        types: &[StateId],
+    ) -> Result<(), String> {
+        let cell = |state: u8| types.get(state as usize).copied().unwrap_or(state as StateId);
+        // checked up front so a bad pattern leaves the grid as it was
+        if let Some(&state) = pattern
+            .cells
+            .iter()
+            .find(|&&state| cell(state) as usize >= self.cell_prescriptors.len())
+        {
+            return Err(format!(
+                "the pattern has state {} but there is no cell type for it",
+                state
+            ));
+        }
+        for py in 0..pattern.height {
+            for (px, &state) in pattern.row(py).iter().enumerate() {
+                let _ = self.paint(x + px as i32, y + py, cell(state));
+            }
+        }
+        Ok(())
+    }
+    /// Reads a rectangle of the grid as a pattern, the reverse of `place_pattern`.
+    /// Cells of types with no pattern state are dead.
+    pub fn pattern(
+        &self,
+        (x, y): (i32, i32),
+        (width, height): (i32, i32),
+        types: &[StateId],
+    ) -> Pattern {
+        let mut cells = vec![0; (width * height) as usize];
+        for ((cx, cy), state, _) in self.iter_region((x, y), (width, height)) {
+            let index = match types.iter().position(|&cell| cell == state) {
+                Some(index) => index,
+                // past the end of `types` ids stand for themselves, like in `place_pattern`
+                None if state as usize >= types.len() => state as usize,
+                None => 0,
+            };
+            cells[((cy - y) * width + cx - x) as usize] = u8::try_from(index).unwrap_or(0);
+        }
+        Pattern {
+            width,
+            height,
+            cells,
+            rule: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(width: i32, height: i32, cells: &[u8]) -> Pattern {
+        Pattern {
+            width,
+            height,
+            cells: cells.to_vec(),
+            rule: None,
+        }
+    }
+
+    fn round_trip(pattern: &Pattern) {
+        let rle = pattern.to_rle();
+        assert_eq!(&Pattern::parse_rle(&rle).unwrap(), pattern, "{}", rle);
+    }
+
+    #[test]
+    fn leading_empty_rows() {
+        let single = pattern(3, 3, &[0, 0, 0, 0, 0, 0, 0, 1, 0]);
+        assert_eq!(single.to_rle(), "x = 3, y = 3\n2$bo!\n");
+        round_trip(&single);
+        round_trip(&pattern(2, 4, &[0, 0, 1, 0, 0, 0, 0, 1]));
+    }
+
+    #[test]
+    fn glider_round_trip() {
+        let mut glider = pattern(3, 3, &[0, 1, 0, 0, 0, 1, 1, 1, 1]);
+        assert_eq!(glider.to_rle(), "x = 3, y = 3\nbo$2bo$3o!\n");
+        glider.rule = Some("B3/S23".to_string());
+        round_trip(&glider);
+        round_trip(&pattern(2, 2, &[0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn multistate_letters() {
+        let states = pattern(4, 2, &[1, 24, 25, 255, 0, 0, 0, 2]);
+        let rle = states.to_rle();
+        assert!(rle.contains("AXpAyO$3.B!"), "{}", rle);
+        round_trip(&states);
+        assert_eq!(Pattern::parse_rle("x = 2, y = 1\npAyO!").unwrap().cells, [25, 255]);
+    }
+
+    #[test]
+    fn plaintext() {
+        let parsed = Pattern::parse_cells("!Name: blinker\n.O\n.O\n.O\n").unwrap();
+        assert_eq!(parsed, pattern(2, 3, &[0, 1, 0, 1, 0, 1]));
+    }
+
+    #[test]
+    fn bad_rle() {
+        assert!(Pattern::parse_rle("x = 3, y = 3\n3o$bz!").is_err());
+        assert!(Pattern::parse_rle("x = 3, y = 3\n3o$bq!").is_err());
+        assert!(Pattern::parse_rle("x = 3, y = 1\nzO!").is_err());
+    }
+
+    #[test]
+    fn huge_rle() {
+        assert!(Pattern::parse_rle("99999999999999999999999o!").is_err());
+        assert!(Pattern::parse_rle("16385o!").is_err());
+        assert!(Pattern::parse_rle("9000$9000$o!").is_err());
+        assert!(Pattern::parse_rle("x = 100000, y = 100000\no!").is_err());
+        assert_eq!(Pattern::parse_rle("16384o!").unwrap().width, 16384);
+    }
+
+    #[test]
+    fn unknown_states_paint_nothing() {
+        let (mut grid, _) = Grid::new(3, 1);
+        let white = crate::grid::Color { r: 255, g: 255, b: 255, a: 255 };
+        let neighborhood = crate::neighborhood::Neighborhood::default();
+        grid.add_state(white, None, 1, "live".to_string(), Vec::new(), neighborhood);
+        assert!(grid.place_pattern(&pattern(3, 1, &[1, 1, 2]), (0, 0), &[]).is_err());
+        assert_eq!(grid.iter_region((0, 0), (3, 1)).filter(|&(_, state, _)| state != 0).count(), 0);
+    }
+}
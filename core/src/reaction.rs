@@ -0,0 +1,105 @@
+//! Reactions between neighbouring cells, the `[[reaction]]` list of a rule file.
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::grid::{Field, Grid, StateId};
+
+/// Two neighbouring cells of types `a` and `b` turning into others.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reaction {
+    pub a: StateId,
+    pub b: StateId,
+    /// what the `a` cell becomes, None to leave it
+    pub into_a: Option<StateId>,
+    /// what the `b` cell becomes, None to leave it
+    pub into_b: Option<StateId>,
+    /// the chance of reacting each time an `a` cell next to a `b` one is updated
+    pub chance: f64,
+    /// cell types of which one must be around the `a` cell, any if empty
+    pub catalysts: Vec<StateId>,
+    /// bounds of the `temperature` field of the `a` cell
+    pub min_temperature: Option<f64>,
+    pub max_temperature: Option<f64>,
+}
+
+impl Reaction {
+    /// Reads a `{ a, b, into_a, into_b, chance, catalyst, min_temperature, max_temperature }` table,
+    /// naming the cell types of the grid.
+    pub fn from_toml(value: &toml::Value, grid: &Grid) -> Result<Self, String> {
+        let table = value.as_table().ok_or("a reaction is a table")?;
+        let state = |name: &toml::Value| {
+            let name = name.as_str().ok_or("cell types are given by name")?;
+            grid.state_by_name(name).ok_or_else(|| format!("unknown cell type {}", name))
+        };
+        let number = |key: &str| -> Result<Option<f64>, String> {
+            let Some(value) = table.get(key) else {
+                return Ok(None);
+            };
+            let number = value.as_float().or_else(|| value.as_integer().map(|v| v as f64));
+            number.map(Some).ok_or(format!("{} is not a number", key))
+        };
+        let catalysts = match table.get("catalyst") {
+            None => Vec::new(),
+            Some(toml::Value::Array(names)) => names.iter().map(state).collect::<Result<_, _>>()?,
+            Some(name) => vec![state(name)?],
+        };
+        let chance = number("chance")?.unwrap_or(1.0);
+        if !(0.0..=1.0).contains(&chance) {
+            return Err("chance is a number from 0 to 1".to_string());
+        }
+        Ok(Reaction {
+            a: state(table.get("a").ok_or("a reaction needs an a")?)?,
+            b: state(table.get("b").ok_or("a reaction needs a b")?)?,
+            into_a: table.get("into_a").map(state).transpose()?,
+            into_b: table.get("into_b").map(state).transpose()?,
+            chance,
+            catalysts,
+            min_temperature: number("min_temperature")?,
+            max_temperature: number("max_temperature")?,
+        })
+    }
+}
+
+impl Grid {
+    /// Tries the reactions of the cell at a position as their `a`, with a random neighbour as `b`,
+    /// and applies the first that happens. Returns whether the cell itself was turned into another.
+    pub fn apply_reactions(&mut self, (x, y): (i32, i32), rng: &mut impl Rng) -> bool {
+        let Some(state) = self.state_at(x, y) else {
+            return false;
+        };
+        if !self.reactions.iter().any(|reaction| reaction.a == state) {
+            return false;
+        }
+        let around: Vec<((i32, i32), StateId)> = self
+            .neighbors((x, y), &self.cell_prescriptors[state as usize].neighborhood)
+            .filter(|&(offset, _)| offset != (0, 0))
+            .collect();
+        let temperature = match self.field(x, y, "temperature") {
+            Some(Field::Int(t)) => Some(t as f64),
+            Some(Field::Float(t)) => Some(t),
+            _ => None,
+        };
+        let reaction = self.reactions.iter().filter(|reaction| reaction.a == state).find_map(|reaction| {
+            let catalyzed = reaction.catalysts.is_empty()
+                || around.iter().any(|(_, cell)| reaction.catalysts.contains(cell));
+            let warm = reaction.min_temperature.is_none_or(|min| temperature.is_some_and(|t| t >= min));
+            let cool = reaction.max_temperature.is_none_or(|max| temperature.is_some_and(|t| t <= max));
+            if !(catalyzed && warm && cool) {
+                return None;
+            }
+            let partners: Vec<_> = around.iter().filter(|(_, cell)| *cell == reaction.b).collect();
+            let &&((dx, dy), _) = partners.choose(rng)?;
+            rng.gen_bool(reaction.chance).then_some(((dx, dy), reaction.into_a, reaction.into_b))
+        });
+        let Some(((dx, dy), into_a, into_b)) = reaction else {
+            return false;
+        };
+        if let Some(into_b) = into_b {
+            let _ = self.set(x + dx, y + dy, into_b);
+        }
+        if let Some(into_a) = into_a {
+            let _ = self.set(x, y, into_a);
+        }
+        into_a.is_some()
+    }
+}
@@ -0,0 +1,377 @@
+use std::path::Path;
+
+use rand::Rng;
+use rlua::Lua;
+use toml::Table;
+
+use crate::behavior::Behavior;
+use crate::grid::{Boundary, Color, Field, Grid, SharedGrid, StateId, OUT_OF_BOUNDS};
+use crate::image::{parse_hex, Image};
+use crate::neighborhood::Neighborhood;
+use crate::pattern::Pattern;
+use crate::reaction::Reaction;
+use crate::rewrite::Rewrite;
+use crate::rule::Rule;
+use crate::scheduler::Mode;
+
+pub struct Options {
+    /// name and id of every cell type
+    pub table: Vec<(String, usize)>,
+    /// how cells are picked for updating
+    pub mode: Mode,
+    /// the seed of the run's random number generator
+    pub seed: u64,
+    /// scripts that failed to compile or run, the rest of the rule file still works
+    pub errors: Vec<String>,
+}
+
+/// What the caller wants instead of the values in the rule file.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    /// a fixed seed for a reproducible run, replacing `[entry] seed`
+    pub seed: Option<u64>,
+    /// width and height, replacing the ones in `[entry]`
+    pub size: Option<(i32, i32)>,
+}
+
+/// Reads a rule file into a grid and the Lua state its scripts run in. Mistakes in the rule file are errors,
+/// scripts that fail to compile or an `init` that fails end up in `Options::errors` instead.
+pub fn read_grid(path: &str, settings: &Settings) -> Result<(Lua, SharedGrid, Options), String> {
+    // read file
+    let file = std::fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+    let mut options = Options {
+        table: Vec::new(),
+        mode: Mode::Async,
+        seed: 0,
+        errors: Vec::new(),
+    };
+    // parse file
+    let parsed: Table = toml::from_str(&file).map_err(|err| format!("Failed to parse {}: {}", path, err))?;
+    // create grid
+    let entry = parsed
+        .get("entry")
+        .and_then(|entry| entry.as_table())
+        .ok_or("The rule file needs an [entry] table")?;
+    // without a seed every run is different
+    options.seed = match (settings.seed, entry.get("seed")) {
+        (Some(seed), _) => seed,
+        (None, Some(seed)) => integer(seed, "[entry] seed")? as u64,
+        (None, None) => rand::thread_rng().gen_range(0..u32::MAX) as u64,
+    };
+    // an image is looked up next to the rule file and sizes the grid
+    let image = match entry.get("image") {
+        Some(image) => {
+            let image = Path::new(path).parent().unwrap_or(Path::new("")).join(string(image, "[entry] image")?);
+            Some(Image::read_png(&image.to_string_lossy())?)
+        }
+        None => None,
+    };
+    let (width, height) = match (settings.size, &image) {
+        (Some(size), _) => size,
+        (None, Some(image)) => (image.width, image.height),
+        (None, None) => (
+            entry.get("width").map_or(Ok(150), |w| integer(w, "[entry] width"))? as i32,
+            entry.get("height").map_or(Ok(150), |h| integer(h, "[entry] height"))? as i32,
+        ),
+    };
+    if width <= 0 || height <= 0 {
+        return Err("The grid size must be positive".to_string());
+    }
+    let init = entry.get("init").map(|init| string(init, "[entry] init")).transpose()?;
+    let (mut grid, lua) = Grid::new(width, height);
+    // create states
+    let states = parsed
+        .get("cell")
+        .and_then(|cell| cell.as_table())
+        .ok_or("The rule file needs cell types, [cell.name] tables")?;
+    // air takes the first id and OUT_OF_BOUNDS the last
+    if states.len() >= OUT_OF_BOUNDS as usize {
+        return Err("Too many cell types".to_string());
+    }
+    // rules and rewrites name other cell types, they are resolved once all types exist
+    let mut rules = Vec::new();
+    let mut rewrites = Vec::new();
+    for (name, state) in states {
+        let state = state.as_table().ok_or_else(|| format!("cell.{} must be a table", name))?;
+        let color = state
+            .get("color")
+            .and_then(|color| color.as_array())
+            .filter(|color| color.len() >= 3)
+            .ok_or_else(|| format!("{} needs a color, [r, g, b] from 0 to 1", name))?;
+        let channel = |i: usize| {
+            let channel = color[i].as_float().or_else(|| color[i].as_integer().map(|c| c as f64));
+            channel.map(|c| (c * 255.) as u8).ok_or_else(|| format!("The color of {} is made of numbers", name))
+        };
+        /*let color = match color {
+            "red" => raylib::Color::RED,
+            "green" => raylib::Color::GREEN,
+            "blue" => raylib::Color::BLUE,
+            "yellow" => raylib::Color::YELLOW,
+            "orange" => raylib::Color::ORANGE,
+            "purple" => raylib::Color::PURPLE,
+            "brown" => raylib::Color::BROWN,
+            "white" => raylib::Color::WHITE,
+            "black" => raylib::Color::BLACK,
+            _ => raylib::Color::BLANK,
+        };*/
+        let matter = state
+            .get("state")
+            .and_then(|matter| matter.as_integer())
+            .ok_or_else(|| format!("{} needs a state, its state of matter", name))? as u8;
+        let update = match state.get("update") {
+            Some(update) => Some(string(update, "An update script")?.to_string()),
+            None => None,
+        };
+        // per-cell fields, typed by their default value
+        let mut fields = Vec::new();
+        if let Some(table) = state.get("fields") {
+            let table = table.as_table().ok_or_else(|| format!("The fields of {} are a table", name))?;
+            for (field, value) in table {
+                // the kernel table of a cell already has these keys
+                if field == "state" || field == "matter" {
+                    return Err(format!("{} can't have a field named {}", name, field));
+                }
+                let value = match value {
+                    toml::Value::Integer(v) => Field::Int(*v),
+                    toml::Value::Float(v) => Field::Float(*v),
+                    toml::Value::Boolean(v) => Field::Bool(*v),
+                    _ => return Err(format!("Field {} of {} must be a number or a boolean", field, name)),
+                };
+                fields.push((field.to_string(), value));
+            }
+        }
+        let rule = match state.get("rule") {
+            Some(_) if update.is_some() => return Err(format!("{} has both a rule and an update script", name)),
+            Some(rule) => {
+                let rule = Rule::parse(string(rule, "A rule")?);
+                Some(rule.map_err(|err| format!("Bad rule of {}: {}", name, err))?)
+            }
+            None => None,
+        };
+        // rules count the 8 cells around, without the cell itself
+        let neighborhood = match state.get("neighborhood") {
+            Some(nh) => Neighborhood::from_toml(nh).map_err(|err| format!("Bad neighborhood of {}: {}", name, err))?,
+            None if rule.is_some() => Neighborhood::moore(1, false),
+            None => Neighborhood::default(),
+        };
+        grid.add_state(
+            Color {
+                r: channel(0)?,
+                g: channel(1)?,
+                b: channel(2)?,
+                a: 255,
+            },
+            update,
+            matter,
+            name.to_string(),
+            fields,
+            neighborhood,
+        );
+        let me = grid.cell_prescriptors.last_mut().unwrap();
+        me.behavior = Behavior::from_toml(state).map_err(|err| format!("Bad behavior of {}: {}", name, err))?;
+        if let Some(density) = state.get("density") {
+            me.density = density
+                .as_float()
+                .or_else(|| density.as_integer().map(|d| d as f64))
+                .ok_or_else(|| format!("The density of {} is not a number", name))?;
+        }
+        if let Some(rule) = rule {
+            rules.push((grid.cell_prescriptors.len() - 1, rule, state.get("dead"), state.get("dying")));
+        }
+        if let Some(rewrite) = state.get("rewrite") {
+            let rewrite = rewrite
+                .as_array()
+                .ok_or_else(|| format!("{}.rewrite is a list, write [[cell.{}.rewrite]]", name, name))?;
+            rewrites.push((grid.cell_prescriptors.len() - 1, rewrite));
+        }
+    }
+    let find = |grid: &Grid, name: &toml::Value, what: &str| {
+        let name = string(name, &format!("A {} cell type", what))?;
+        grid.state_by_name(name).ok_or_else(|| format!("Unknown {} cell type {}", what, name))
+    };
+    for (alive, mut rule, dead, dying) in rules {
+        rule.dead = dead.map(|dead| find(&grid, dead, "dead")).transpose()?.unwrap_or(0);
+        let me = grid.cell_prescriptors[alive].clone();
+        rule.dying = match dying {
+            Some(dying) => dying
+                .as_array()
+                .ok_or_else(|| format!("The dying types of {} are a list", me.name))?
+                .iter()
+                .map(|name| find(&grid, name, "dying"))
+                .collect::<Result<_, _>>()?,
+            // Generations rules without dying types get ones that fade to black, named live1, live2 and so on
+            None => (1..rule.states - 1)
+                .map(|i| {
+                    let fade = |channel: u8| (channel as u32 * (rule.states - 1 - i) / (rule.states - 1)) as u8;
+                    let color = Color { r: fade(me.color.r), g: fade(me.color.g), b: fade(me.color.b), a: 255 };
+                    let name = format!("{}{}", me.name, i);
+                    grid.add_state(color, None, me.matter, name, Vec::new(), Neighborhood::default());
+                    (grid.cell_prescriptors.len() - 1) as StateId
+                })
+                .collect(),
+        };
+        if rule.dying.len() as u32 != rule.states - 2 {
+            return Err(format!(
+                "The rule of {} has {} dying states, but {} dying types are given",
+                me.name,
+                rule.states - 2,
+                rule.dying.len()
+            ));
+        }
+        grid.cell_prescriptors[alive].rule = Some(rule);
+    }
+    if grid.cell_prescriptors.len() >= OUT_OF_BOUNDS as usize {
+        return Err("Too many cell types".to_string());
+    }
+    for (state, list) in rewrites {
+        let parsed = list
+            .iter()
+            .enumerate()
+            .map(|(i, rewrite)| {
+                Rewrite::from_toml(rewrite, &grid)
+                    .map_err(|err| format!("Bad rewrite {} of {}: {}", i + 1, grid.cell_prescriptors[state].name, err))
+            })
+            .collect::<Result<_, _>>()?;
+        grid.cell_prescriptors[state].rewrites = parsed;
+    }
+    for (i, reaction) in parsed.get("reaction").and_then(|r| r.as_array()).into_iter().flatten().enumerate() {
+        let reaction = Reaction::from_toml(reaction, &grid).map_err(|err| format!("Bad reaction {}: {}", i + 1, err))?;
+        grid.reactions.push(reaction);
+    }
+
+    // the boundary can name a cell type, so it is read after the states
+    grid.boundary = match entry.get("boundary").map(|b| string(b, "[entry] boundary")).transpose()? {
+        None | Some("closed") => Boundary::Closed,
+        Some("wrap") => Boundary::Wrap,
+        Some("void") => Boundary::Void,
+        Some("mirror") => Boundary::Mirror,
+        Some("wall") => {
+            let name = entry.get("wall").ok_or("A wall boundary needs a wall cell type")?;
+            Boundary::Wall(find(&grid, name, "wall")?)
+        }
+        Some(other) => return Err(format!("Unknown boundary {}", other)),
+    };
+
+    if let Some(image) = &image {
+        let palette: Vec<(Color, StateId)> = match entry.get("palette") {
+            Some(palette) => palette
+                .as_table()
+                .ok_or("[entry.palette] maps colors to cell types")?
+                .iter()
+                .map(|(color, name)| {
+                    let color =
+                        parse_hex(color).ok_or_else(|| format!("Palette colors look like #ff8800, not {}", color))?;
+                    Ok((color, find(&grid, name, "palette")?))
+                })
+                .collect::<Result<_, String>>()?,
+            None => grid.palette(),
+        };
+        grid.import_image(image, &palette);
+    }
+    // patterns go on top of the image, their files are looked up next to the rule file too
+    for pattern in entry.get("pattern").and_then(|p| p.as_array()).into_iter().flatten() {
+        let file = string(pattern.get("file").ok_or("A pattern needs a file")?, "The file of a pattern")?;
+        let file = Path::new(path).parent().unwrap_or(Path::new("")).join(file);
+        let loaded = Pattern::read(&file.to_string_lossy())?;
+        let at = |key: &str| pattern.get(key).map_or(Ok(0), |v| integer(v, "The position of a pattern"));
+        let types: Vec<StateId> = pattern
+            .get("types")
+            .and_then(|types| types.as_array())
+            .into_iter()
+            .flatten()
+            .map(|name| find(&grid, name, "pattern"))
+            .collect::<Result<_, _>>()?;
+        grid.place_pattern(&loaded, (at("x")? as i32, at("y")? as i32), &types)
+            .map_err(|err| format!("{}: {}", file.display(), err))?;
+    }
+    let mode = entry.get("mode").map(|mode| string(mode, "[entry] mode")).transpose()?;
+    if let Some(mode) = mode {
+        options.mode = Mode::from_name(mode).ok_or_else(|| format!("Unknown mode {}", mode))?;
+    }
+    // shifted blocks wrap around, which only splits the grid into blocks if both sides are even
+    if options.mode == Mode::Margolus && grid.boundary == Boundary::Wrap && (width % 2 != 0 || height % 2 != 0) {
+        let size = format!("{}x{}", width, height);
+        return Err(format!("Margolus mode with a wrap boundary needs an even width and height, not {}", size));
+    }
+    if options.mode == Mode::Margolus {
+        options.errors.extend(ignored_by_blocks(&grid));
+    }
+
+    // send grid as userdata to lua
+    lua.context(|lua_ctx| {
+        let globals = lua_ctx.globals();
+        globals.set("OUT_OF_BOUNDS", OUT_OF_BOUNDS)?;
+        for (i, cell) in grid.cell_prescriptors.iter().enumerate() {
+            globals.set(cell.name.as_str(), i)?;
+            options.table.push((cell.name.clone(), i));
+        }
+        Ok(())
+    })
+    .map_err(|err: rlua::Error| err.to_string())?;
+    options.errors.extend(grid.compile(&lua));
+
+    let grid = SharedGrid::new(grid, options.seed);
+    lua.context(|ctx| ctx.globals().set("grid", grid.clone())).map_err(|err| err.to_string())?;
+    grid.install_random(&lua).map_err(|err| err.to_string())?;
+
+    // seed the world now that every cell type has a global
+    if let Some(init) = init {
+        if let Err(err) = Grid::init(&lua, &grid, init) {
+            options.errors.push(format!("Error in [entry].init: {}", err));
+        }
+    }
+    // the back buffer starts as a copy of the world init made
+    grid.lock().set_sync(options.mode == Mode::Sync);
+    Ok((lua, grid, options))
+}
+
+/// What margolus blocks leave out of the rule file, they only run update scripts.
+fn ignored_by_blocks(grid: &Grid) -> Vec<String> {
+    let mut ignored = Vec::new();
+    for cell in &grid.cell_prescriptors {
+        if let Some(rule) = &cell.rule {
+            let rule = format!("rule {} of {}", rule, cell.name);
+            ignored.push(format!("The {} is ignored, margolus blocks only run update scripts", rule));
+        }
+        if cell.behavior.is_some() {
+            ignored.push(format!("The behavior of {} is ignored, margolus blocks only run update scripts", cell.name));
+        }
+        if !cell.rewrites.is_empty() {
+            ignored.push(format!("The rewrites of {} are ignored, margolus blocks only run update scripts", cell.name));
+        }
+    }
+    if !grid.reactions.is_empty() {
+        ignored.push("The reactions are ignored, margolus blocks only run update scripts".to_string());
+    }
+    ignored
+}
+
+/// The string a value of the rule file holds.
+fn string<'a>(value: &'a toml::Value, what: &str) -> Result<&'a str, String> {
+    value.as_str().ok_or_else(|| format!("{} must be a string", what))
+}
+
+/// The integer a value of the rule file holds.
+fn integer(value: &toml::Value, what: &str) -> Result<i64, String> {
+    value.as_integer().ok_or_else(|| format!("{} must be a whole number", what))
+}
+
+/// Reads the rule file again for a running world. The cell types, scripts, colors and reactions
+/// are replaced and every cell is carried over by the name of its type, see `Grid::adopt`.
+/// The world keeps its size and its random number generator. A broken rule file changes nothing.
+pub fn reload_grid(path: &str, old: &SharedGrid) -> Result<(Lua, SharedGrid, Options), String> {
+    let size = {
+        let old = old.lock();
+        (old.width, old.height)
+    };
+    let settings = Settings {
+        seed: Some(0),
+        size: Some(size),
+    };
+    let (lua, grid, mut options) = read_grid(path, &settings)?;
+    for gone in grid.lock().adopt(&old.lock()) {
+        options.errors.push(format!("Cell type {} is gone, its cells became air", gone));
+    }
+    *grid.rng() = old.rng().clone();
+    Ok((lua, grid, options))
+}
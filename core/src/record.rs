@@ -0,0 +1,119 @@
+//! Recording a run as an animated GIF or a numbered sequence of PNGs.
+
+use std::{fs::File, io::BufWriter, path::Path};
+
+use crate::grid::Grid;
+
+/// Captures a frame of the grid every few generations.
+pub struct Recorder {
+    output: Output,
+    /// generations between frames
+    pub every: u64,
+    /// pixels per cell
+    pub scale: i32,
+    /// frames written so far
+    pub frames: u64,
+}
+
+enum Output {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        path: String,
+        /// the cell type colors when there are few enough of them to index frames directly
+        palette: Option<Vec<u8>>,
+    },
+    Pngs(String),
+}
+
+impl Recorder {
+    /// Records into `path`, an animated GIF if it ends in `.gif` and
+    /// PNGs numbered like `path-000001.png` otherwise.
+    /// The grid decides the size of the frames, it can't change while recording.
+    pub fn new(path: &str, grid: &Grid, every: u64, scale: i32) -> Result<Self, String> {
+        let output = if path.ends_with(".gif") {
+            let (width, height) = (grid.width * scale, grid.height * scale);
+            if width > u16::MAX as i32 || height > u16::MAX as i32 {
+                return Err(format!("{}x{} is too large for a GIF", width, height));
+            }
+            let palette = (grid.cell_prescriptors.len() <= 256).then(|| {
+                grid.cell_prescriptors
+                    .iter()
+                    .flat_map(|cell| [cell.color.r, cell.color.g, cell.color.b])
+                    .collect::<Vec<u8>>()
+            });
+            let file = File::create(path).map_err(|err| format!("Failed to write {}: {}", path, err))?;
+            let encoder = gif::Encoder::new(
+                BufWriter::new(file),
+                width as u16,
+                height as u16,
+                palette.as_deref().unwrap_or(&[]),
+            )
+            .and_then(|mut encoder| encoder.set_repeat(gif::Repeat::Infinite).map(|_| encoder))
+            .map_err(|err| format!("Failed to write {}: {}", path, err))?;
+            Output::Gif {
+                encoder,
+                path: path.to_string(),
+                palette,
+            }
+        } else {
+            Output::Pngs(path.to_string())
+        };
+        Ok(Recorder {
+            output,
+            every: every.max(1),
+            scale,
+            frames: 0,
+        })
+    }
+    /// Captures a frame if the generation is one of those to record.
+    pub fn generation(&mut self, generation: u64, grid: &Grid) -> Result<(), String> {
+        if generation.is_multiple_of(self.every) {
+            self.capture(grid)?;
+        }
+        Ok(())
+    }
+    /// Captures a frame now.
+    pub fn capture(&mut self, grid: &Grid) -> Result<(), String> {
+        self.frames += 1;
+        match &mut self.output {
+            Output::Gif { encoder, path, palette } => {
+                let (width, height) = ((grid.width * self.scale) as u16, (grid.height * self.scale) as u16);
+                let mut frame = if palette.is_some() {
+                    // one palette index per cell, scaled up
+                    let mut indices = Vec::with_capacity(width as usize * height as usize);
+                    for y in 0..grid.height {
+                        let row: Vec<u8> = grid
+                            .iter_region((0, y), (grid.width, 1))
+                            .flat_map(|(_, state, _)| std::iter::repeat_n(state as u8, self.scale as usize))
+                            .collect();
+                        for _ in 0..self.scale {
+                            indices.extend_from_slice(&row);
+                        }
+                    }
+                    gif::Frame::from_indexed_pixels(width, height, indices, None)
+                } else {
+                    let image = grid.to_image(self.scale);
+                    let rgb: Vec<u8> = image.pixels.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+                    gif::Frame::from_rgb_speed(width, height, &rgb, 10)
+                };
+                // hundredths of a second
+                frame.delay = 10;
+                encoder
+                    .write_frame(&frame)
+                    .map_err(|err| format!("Failed to write {}: {}", path, err))
+            }
+            Output::Pngs(path) => grid.to_image(self.scale).write_png(&numbered(path, self.frames)),
+        }
+    }
+}
+
+/// `world.txt` becomes `world-000100.txt` for number 100.
+pub fn numbered(path: &str, number: u64) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("grid");
+    let name = match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => format!("{}-{:06}.{}", stem, number, ext),
+        None => format!("{}-{:06}", stem, number),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
@@ -0,0 +1,228 @@
+//! Pattern rewrite rules: "if the cells around me look like this, make them look like that",
+//! matched natively before the update script of a cell type.
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::grid::{Field, Grid, StateId};
+
+/// What a cell of the `match` pattern accepts.
+#[derive(Debug, Clone, PartialEq)]
+enum Matcher {
+    /// `*`: any cell, even outside of the grid
+    Any,
+    /// `@`: the updated cell itself
+    This,
+    /// `sand` or `air|water`: one of the cell types
+    Types(Vec<StateId>),
+    /// `matter>1` and the like: matter in the inclusive range
+    Matter(u8, u8),
+}
+
+/// What a cell of the `into` pattern becomes.
+#[derive(Debug, Clone, PartialEq)]
+enum Output {
+    /// `*`: stays as it is
+    Keep,
+    /// `$N`: the Nth cell of the match, fields included
+    Copy(usize),
+    /// a cell type name: a fresh cell of the type
+    Type(StateId),
+}
+
+/// The cells of a pattern as their offset from the updated cell, with what they must match
+/// and what they become, in the row order of the written pattern.
+type Variant = Vec<((i32, i32), Matcher, Output)>;
+
+/// One `[[cell.X.rewrite]]` entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rewrite {
+    /// the pattern and its mirrored and rotated copies
+    variants: Vec<Variant>,
+    /// the chance of rewriting when the pattern matches
+    pub probability: f64,
+}
+
+impl Rewrite {
+    /// Reads a `{ match, into, symmetry, probability }` table, naming the cell types of the grid.
+    pub fn from_toml(value: &toml::Value, grid: &Grid) -> Result<Self, String> {
+        let table = value.as_table().ok_or("a rewrite is a table")?;
+        let rows = |key: &str| -> Result<Vec<Vec<String>>, String> {
+            table
+                .get(key)
+                .and_then(|rows| rows.as_array())
+                .ok_or_else(|| format!("a rewrite needs `{}`, a list of rows", key))?
+                .iter()
+                .map(|row| {
+                    let row = row.as_str().ok_or("pattern rows are strings")?;
+                    Ok(row.split_whitespace().map(String::from).collect())
+                })
+                .collect()
+        };
+        let (input, output) = (rows("match")?, rows("into")?);
+        let shape = |rows: &Vec<Vec<String>>| rows.iter().map(|row| row.len()).collect::<Vec<_>>();
+        if shape(&input) != shape(&output) {
+            return Err("`match` and `into` must have the same shape".to_string());
+        }
+        let state = |name: &str| grid.state_by_name(name).ok_or_else(|| format!("unknown cell type {}", name));
+
+        let mut pattern = Vec::new();
+        let mut anchor = None;
+        let count = input.iter().map(|row| row.len()).sum::<usize>();
+        for (y, (input, output)) in input.iter().zip(&output).enumerate() {
+            for (x, (input, output)) in input.iter().zip(output).enumerate() {
+                let matcher = match input.as_str() {
+                    "*" => Matcher::Any,
+                    "@" => {
+                        if anchor.replace((x as i32, y as i32)).is_some() {
+                            return Err("only one cell of `match` can be @".to_string());
+                        }
+                        Matcher::This
+                    }
+                    token => match token.strip_prefix("matter") {
+                        Some(test) => matter(test).ok_or_else(|| format!("bad matter test {}", token))?,
+                        None => Matcher::Types(token.split('|').map(state).collect::<Result<_, _>>()?),
+                    },
+                };
+                let output = match output.as_str() {
+                    "*" => Output::Keep,
+                    token => match token.strip_prefix('$') {
+                        Some(n) => match n.parse::<usize>() {
+                            Ok(n) if (1..=count).contains(&n) => Output::Copy(n - 1),
+                            _ => return Err(format!("{} is not a cell of `match`", token)),
+                        },
+                        None => Output::Type(state(token)?),
+                    },
+                };
+                pattern.push(((x as i32, y as i32), matcher, output));
+            }
+        }
+        let (ax, ay) = anchor.ok_or("one cell of `match` must be @, the updated cell")?;
+        for ((x, y), _, _) in &mut pattern {
+            *x -= ax;
+            *y -= ay;
+        }
+
+        // every transform is applied to the offsets, so `$N` still means the same cell
+        let symmetry = table.get("symmetry").map(|s| s.as_str().ok_or("symmetry is a name")).transpose()?;
+        let transforms: Vec<(u32, bool)> = match symmetry {
+            None | Some("none") => vec![(0, false)],
+            Some("mirror") => vec![(0, false), (0, true)],
+            Some("rotate") => (0..4).map(|turns| (turns, false)).collect(),
+            Some("all") => (0..8).map(|n| (n % 4, n >= 4)).collect(),
+            Some(other) => return Err(format!("unknown symmetry {}, expected none, mirror, rotate or all", other)),
+        };
+        let mut variants: Vec<Variant> = Vec::new();
+        for (turns, mirrored) in transforms {
+            let variant = pattern
+                .iter()
+                .map(|(offset, matcher, output)| {
+                    (transform(*offset, turns, mirrored), matcher.clone(), output.clone())
+                })
+                .collect();
+            if !variants.contains(&variant) {
+                variants.push(variant);
+            }
+        }
+
+        let probability = match table.get("probability") {
+            Some(p) => p
+                .as_float()
+                .or_else(|| p.as_integer().map(|p| p as f64))
+                .filter(|p| (0.0..=1.0).contains(p))
+                .ok_or("probability is a number from 0 to 1")?,
+            None => 1.0,
+        };
+        Ok(Rewrite { variants, probability })
+    }
+}
+
+/// Mirrors an offset left to right if asked, then turns it clockwise by quarter turns.
+fn transform((mut x, mut y): (i32, i32), turns: u32, mirrored: bool) -> (i32, i32) {
+    if mirrored {
+        x = -x;
+    }
+    for _ in 0..turns {
+        (x, y) = (-y, x);
+    }
+    (x, y)
+}
+
+/// Parses the `>1` of `matter>1`, also `<`, `=`, `>=` and `<=`.
+fn matter(test: &str) -> Option<Matcher> {
+    let (low, high) = if let Some(n) = test.strip_prefix(">=") {
+        (n.parse().ok()?, u8::MAX)
+    } else if let Some(n) = test.strip_prefix("<=") {
+        (0, n.parse().ok()?)
+    } else if let Some(n) = test.strip_prefix('>') {
+        (n.parse::<u8>().ok()?.checked_add(1)?, u8::MAX)
+    } else if let Some(n) = test.strip_prefix('<') {
+        (0, n.parse::<u8>().ok()?.checked_sub(1)?)
+    } else {
+        let n = test.strip_prefix('=')?.parse().ok()?;
+        (n, n)
+    };
+    Some(Matcher::Matter(low, high))
+}
+
+impl Grid {
+    /// Tries the rewrites of the type of the cell at a position in order and applies the first that matches
+    /// and passes its probability. When several variants of it match, one is picked at random.
+    /// Returns whether a rewrite was applied.
+    pub fn apply_rewrites(&mut self, (x, y): (i32, i32), rng: &mut impl Rng) -> bool {
+        let Some(state) = self.state_at(x, y) else {
+            return false;
+        };
+        let me = &self.cell_prescriptors[state as usize];
+        let rewrite = me.rewrites.iter().find_map(|rewrite| {
+            let matching: Vec<_> = rewrite
+                .variants
+                .iter()
+                .filter(|variant| {
+                    variant.iter().all(|&((dx, dy), ref matcher, _)| self.matches(matcher, (x + dx, y + dy)))
+                })
+                .collect();
+            let variant = matching.choose(rng)?;
+            rng.gen_bool(rewrite.probability).then_some(*variant)
+        });
+        let Some(variant) = rewrite else {
+            return false;
+        };
+        // read every cell before writing, so `$N` sees the cells as they were matched
+        let matched: Vec<Option<(StateId, Vec<Field>)>> = variant
+            .iter()
+            .map(|&((dx, dy), _, _)| {
+                let state = self.state_at(x + dx, y + dy)?;
+                let data = match self.get(x + dx, y + dy) {
+                    Some((_, data)) => data.to_vec(),
+                    None => self.cell_prescriptors[state as usize].defaults(),
+                };
+                Some((state, data))
+            })
+            .collect();
+        let writes: Vec<_> = variant
+            .iter()
+            .filter_map(|((dx, dy), _, output)| {
+                let cell = match output {
+                    Output::Keep => return None,
+                    Output::Copy(i) => matched[*i].clone()?,
+                    Output::Type(state) => (*state, self.cell_prescriptors[*state as usize].defaults()),
+                };
+                Some(((x + dx, y + dy), cell))
+            })
+            .collect();
+        for ((x, y), (state, data)) in writes {
+            let _ = self.set_cell(x, y, state, data);
+        }
+        true
+    }
+    fn matches(&self, matcher: &Matcher, (x, y): (i32, i32)) -> bool {
+        let Some(state) = self.state_at(x, y) else {
+            return *matcher == Matcher::Any;
+        };
+        match matcher {
+            Matcher::Any | Matcher::This => true,
+            Matcher::Types(types) => types.contains(&state),
+            Matcher::Matter(low, high) => (*low..=*high).contains(&self.cell_prescriptors[state as usize].matter),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Life-like and Generations rules, evaluated without Lua.
+
+use std::fmt;
+
+use crate::grid::{Grid, StateId};
+
+/// A totalistic rule like `B3/S23` or `B2/S/C3`, set as the `rule` of the cell type of alive cells.
+/// Only alive neighbours count, the neighborhood is the one of the alive type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    /// bit n is set if a dead cell with n alive neighbours is born
+    pub birth: u32,
+    /// bit n is set if an alive cell with n alive neighbours survives
+    pub survive: u32,
+    /// the number of states of a Generations rule, 2 for Life-like ones
+    pub states: u32,
+    /// the cell type alive cells are born from and die into, air by default
+    pub dead: StateId,
+    /// the cell types of the dying states of a Generations rule, `states - 2` of them
+    pub dying: Vec<StateId>,
+}
+
+impl Rule {
+    /// Parses `B3/S23`, `B2/S/C3` and the older `23/3` and `/2/3` in S/B/C order.
+    /// The cell types are left at air and no dying ones.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (mut birth, mut survive, mut states) = (None, None, None);
+        let parts: Vec<&str> = text.trim().split('/').collect();
+        let lettered = parts.iter().any(|part| part.starts_with(|c: char| c.is_ascii_alphabetic()));
+        for (i, part) in parts.iter().enumerate() {
+            let (key, digits) = if lettered {
+                let mut chars = part.chars();
+                let key = chars.next().map(|c| c.to_ascii_uppercase());
+                (key, chars.as_str())
+            } else {
+                ([Some('S'), Some('B'), Some('C')].get(i).copied().flatten(), *part)
+            };
+            let slot = match key {
+                Some('B') => &mut birth,
+                Some('S') => &mut survive,
+                Some('C') => {
+                    let count: u32 = digits.parse().map_err(|_| format!("{} is not a number of states", digits))?;
+                    if !(2..=256).contains(&count) {
+                        return Err("a rule has 2 to 256 states".to_string());
+                    }
+                    states = Some(count);
+                    continue;
+                }
+                _ => return Err(format!("unexpected {} in the rule {}, expected B, S or C", part, text)),
+            };
+            if slot.is_some() {
+                return Err(format!("{} is given twice in the rule {}", part, text));
+            }
+            let mut mask = 0;
+            for c in digits.chars() {
+                let n = c.to_digit(10).ok_or_else(|| format!("unexpected {} in the rule {}", c, text))?;
+                mask |= 1 << n;
+            }
+            *slot = Some(mask);
+        }
+        Ok(Rule {
+            birth: birth.ok_or_else(|| format!("the rule {} has no B part", text))?,
+            survive: survive.ok_or_else(|| format!("the rule {} has no S part", text))?,
+            states: states.unwrap_or(2),
+            dead: 0,
+            dying: Vec::new(),
+        })
+    }
+    pub fn born(&self, alive: usize) -> bool {
+        alive < 32 && self.birth >> alive & 1 == 1
+    }
+    pub fn survives(&self, alive: usize) -> bool {
+        alive < 32 && self.survive >> alive & 1 == 1
+    }
+}
+
+impl fmt::Display for Rule {
+    /// The rule in B/S notation, with a C part for Generations rules.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = |mask: u32| (0..10).filter(|n| mask >> n & 1 == 1).map(|n| n.to_string()).collect::<String>();
+        write!(f, "B{}/S{}", digits(self.birth), digits(self.survive))?;
+        if self.states > 2 {
+            write!(f, "/C{}", self.states)?;
+        }
+        Ok(())
+    }
+}
+
+impl Grid {
+    /// Updates the cell at a position by a rule: the rule of its type, or the rule of
+    /// a type it is the dead or a dying state of. Returns whether a rule took care of the cell,
+    /// if not the update script of its type is up.
+    pub fn apply_rule(&mut self, (x, y): (i32, i32)) -> bool {
+        let Some(state) = self.state_at(x, y) else {
+            return false;
+        };
+        let alive_around = |grid: &Grid, alive: usize| {
+            let neighborhood = &grid.cell_prescriptors[alive].neighborhood;
+            grid.neighbors((x, y), neighborhood).filter(|&(_, cell)| cell as usize == alive).count()
+        };
+        if let Some(rule) = self.cell_prescriptors.get(state as usize).and_then(|cell| cell.rule.as_ref()) {
+            let survives = rule.survives(alive_around(self, state as usize));
+            let next = rule.dying.first().copied().unwrap_or(rule.dead);
+            if !survives {
+                let _ = self.set(x, y, next);
+            }
+            return true;
+        }
+        let next = self.cell_prescriptors.iter().enumerate().find_map(|(alive, cell)| {
+            let rule = cell.rule.as_ref()?;
+            if let Some(i) = rule.dying.iter().position(|&dying| dying == state) {
+                Some(rule.dying.get(i + 1).copied().unwrap_or(rule.dead))
+            } else if rule.dead == state && rule.born(alive_around(self, alive)) {
+                Some(alive as StateId)
+            } else {
+                None
+            }
+        });
+        match next {
+            Some(next) => self.set(x, y, next).is_ok(),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mask(counts: &[u32]) -> u32 {
+        counts.iter().map(|n| 1 << n).sum()
+    }
+
+    #[test]
+    fn life_like() {
+        let life = Rule::parse("B3/S23").unwrap();
+        assert_eq!((life.birth, life.survive, life.states), (mask(&[3]), mask(&[2, 3]), 2));
+        assert!(life.born(3) && !life.born(2));
+        assert!(life.survives(2) && life.survives(3) && !life.survives(4));
+        assert_eq!(Rule::parse("b3/s23").unwrap(), life);
+        assert_eq!(Rule::parse("S23/B3").unwrap(), life);
+        assert_eq!(life.to_string(), "B3/S23");
+    }
+
+    #[test]
+    fn generations() {
+        let brain = Rule::parse("B2/S/C3").unwrap();
+        assert_eq!((brain.birth, brain.survive, brain.states), (mask(&[2]), 0, 3));
+        assert_eq!(brain.to_string(), "B2/S/C3");
+    }
+
+    #[test]
+    fn survival_first_notation() {
+        assert_eq!(Rule::parse("23/3").unwrap(), Rule::parse("B3/S23").unwrap());
+        assert_eq!(Rule::parse("/2/3").unwrap(), Rule::parse("B2/S/C3").unwrap());
+    }
+
+    #[test]
+    fn bad_rules() {
+        for bad in ["", "B3", "S23", "B3/S23/C1", "B3/S23/C300", "B3/S2x", "B3/B3/S2", "X3/S23", "B3/S23/Cx"] {
+            assert!(Rule::parse(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use rand::{seq::SliceRandom, Rng};
+use rlua::Lua;
+
+use crate::grid::{Boundary, Grid, SharedGrid};
+
+/// How the cells of a Grid are picked for updating.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Random cells, one at a time, the result of each update is seen by the next.
+    Async,
+    /// Every cell once per generation, reading the previous generation and
+    /// writing into a back buffer that is shown when the generation is done.
+    Sync,
+    /// Every cell once per sweep in a random order, the result of each update is seen by the next.
+    Sweep,
+    /// 2x2 blocks updated as a unit, every other generation the blocks are shifted by one cell.
+    /// Hands out the top left corner of each block.
+    Margolus,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "async" => Some(Mode::Async),
+            "sync" => Some(Mode::Sync),
+            "sweep" => Some(Mode::Sweep),
+            "margolus" => Some(Mode::Margolus),
+            _ => None,
+        }
+    }
+}
+
+/// Hands out the cells to update, one at a time.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    pub mode: Mode,
+    width: i32,
+    height: i32,
+    /// whether shifted blocks wrap around instead of sticking out of the grid
+    wrap: bool,
+    /// updates done in the current generation
+    cursor: i32,
+    /// the visiting order of the current sweep
+    order: Vec<(i32, i32)>,
+    /// completed generations (or sweeps), in async mode every width * height updates
+    pub generation: u64,
+}
+
+impl Scheduler {
+    pub fn new(mode: Mode, grid: &Grid) -> Self {
+        Scheduler {
+            mode,
+            width: grid.width,
+            height: grid.height,
+            // odd sides can't be split into wrapped blocks, see `read_grid`, their blocks stick out instead
+            wrap: grid.boundary == Boundary::Wrap && grid.width % 2 == 0 && grid.height % 2 == 0,
+            cursor: 0,
+            order: Vec::new(),
+            generation: 0,
+        }
+    }
+    /// Returns the next cell (or block) to update and whether it is the last one of its generation.
+    pub fn next(&mut self, rng: &mut impl Rng) -> ((i32, i32), bool) {
+        let cell = match self.mode {
+            Mode::Async => (rng.gen_range(0..self.width), rng.gen_range(0..self.height)),
+            Mode::Sync => (self.cursor % self.width, self.cursor / self.width),
+            Mode::Sweep => {
+                if self.order.is_empty() {
+                    for y in 0..self.height {
+                        for x in 0..self.width {
+                            self.order.push((x, y));
+                        }
+                    }
+                }
+                if self.cursor == 0 {
+                    self.order.shuffle(rng);
+                }
+                self.order[self.cursor as usize]
+            }
+            Mode::Margolus => {
+                let (start, columns, _) = self.blocks();
+                (start + self.cursor % columns * 2, start + self.cursor / columns * 2)
+            }
+        };
+        self.cursor += 1;
+        let total = match self.mode {
+            Mode::Margolus => {
+                let (_, columns, rows) = self.blocks();
+                columns * rows
+            }
+            _ => self.width * self.height,
+        };
+        let last = self.cursor == total;
+        if last {
+            self.cursor = 0;
+            self.generation += 1;
+        }
+        (cell, last)
+    }
+    /// Updates the next cell (or block), showing the generation once it is done.
+    /// Returns whether that happened.
+    pub fn step(&mut self, lua: &Lua, grid: &SharedGrid) -> bool {
+        let (cell, last) = self.next(&mut *grid.rng());
+        if self.mode == Mode::Margolus {
+            Grid::update_block(lua, grid, cell);
+        } else {
+            Grid::update(lua, grid, cell);
+        }
+        if last {
+            grid.lock().flip();
+        }
+        last
+    }
+    /// The first block corner and the number of blocks across and down in this generation.
+    /// Shifted blocks start outside of the grid unless they wrap around.
+    fn blocks(&self) -> (i32, i32, i32) {
+        let offset = (self.generation % 2) as i32;
+        let start = if self.wrap { offset } else { -offset };
+        (start, (self.width - start + 1) / 2, (self.height - start + 1) / 2)
+    }
+}
@@ -0,0 +1,275 @@
+//! Saving a world to a file and loading it back.
+//!
+//! A snapshot is little endian binary:
+//! - the magic `ATOMSNAP` and a u32 format version
+//! - width and height as u32
+//! - the cell types as a u16 count, then per type its name and its fields
+//!   as a u8 count of (name, u8 type tag) pairs
+//! - the random number generator: its 32 byte seed, u64 stream and u128 word position
+//! - every cell row by row: its u16 type and the values of its fields
+//!
+//! Strings are a u16 length followed by UTF-8. Types and fields are matched by
+//! name when loading, so a rule file can be reordered or gain types and fields.
+
+use std::fs;
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::grid::{Field, SharedGrid};
+
+const MAGIC: &[u8; 8] = b"ATOMSNAP";
+const VERSION: u32 = 1;
+
+impl SharedGrid {
+    /// Writes the world and the state of the random number generator to a file.
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_snapshot()).map_err(|err| format!("Failed to write {}: {}", path, err))
+    }
+    /// Replaces the world with a snapshot file, see `from_snapshot`.
+    pub fn load(&self, path: &str) -> Result<(), String> {
+        let bytes = fs::read(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+        self.from_snapshot(&bytes).map_err(|err| format!("{}: {}", path, err))
+    }
+    pub fn to_snapshot(&self) -> Vec<u8> {
+        let grid = self.lock();
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&(grid.width as u32).to_le_bytes());
+        out.extend_from_slice(&(grid.height as u32).to_le_bytes());
+        out.extend_from_slice(&(grid.cell_prescriptors.len() as u16).to_le_bytes());
+        for cell in &grid.cell_prescriptors {
+            write_str(&mut out, &cell.name);
+            out.push(cell.fields.len() as u8);
+            for (name, value) in &cell.fields {
+                write_str(&mut out, name);
+                out.push(tag(value));
+            }
+        }
+        let rng = self.rng();
+        out.extend_from_slice(&rng.get_seed());
+        out.extend_from_slice(&rng.get_stream().to_le_bytes());
+        out.extend_from_slice(&rng.get_word_pos().to_le_bytes());
+        for (_, state, data) in grid.iter_region((0, 0), (grid.width, grid.height)) {
+            out.extend_from_slice(&state.to_le_bytes());
+            for value in data {
+                match value {
+                    Field::Int(v) => out.extend_from_slice(&v.to_le_bytes()),
+                    Field::Float(v) => out.extend_from_slice(&v.to_le_bytes()),
+                    Field::Bool(v) => out.push(*v as u8),
+                }
+            }
+        }
+        out
+    }
+    /// Replaces the world with a snapshot, taking its size and random number generator.
+    /// Cell types are looked up by name, fields the rule file has but the snapshot
+    /// lacks (or has with another type) get their defaults. The scheduler has to be made anew.
+    pub fn from_snapshot(&self, bytes: &[u8]) -> Result<(), String> {
+        let mut input = Input { bytes };
+        if input.take(8)? != MAGIC {
+            return Err("not a snapshot".to_string());
+        }
+        let version = input.u32()?;
+        if version != VERSION {
+            return Err(format!("snapshot version {} is not supported, expected {}", version, VERSION));
+        }
+        let width = input.u32()? as i32;
+        let height = input.u32()? as i32;
+        if width <= 0 || height <= 0 {
+            return Err("the grid size must be positive".to_string());
+        }
+        let mut grid = self.lock();
+        // the id and field order in this rule file of every type in the snapshot
+        let mut types = Vec::new();
+        for _ in 0..input.u16()? {
+            let name = input.str()?;
+            let mut fields = Vec::new();
+            for _ in 0..input.u8()? {
+                fields.push((input.str()?, input.u8()?));
+            }
+            let Some(state) = grid.state_by_name(&name) else {
+                return Err(format!("cell type {} is not in the rule file", name));
+            };
+            let me = &grid.cell_prescriptors[state as usize];
+            // where each snapshot field goes, None if the rule file dropped it
+            let places = fields
+                .iter()
+                .map(|(field, tag_of)| me.field_index(field).filter(|&i| tag(&me.fields[i].1) == *tag_of))
+                .collect::<Vec<_>>();
+            types.push((state, fields, places, me.defaults()));
+        }
+        let seed = input.take(32)?.try_into().unwrap();
+        let stream = input.u64()?;
+        let word_pos = u128::from_le_bytes(input.take(16)?.try_into().unwrap());
+
+        // every cell takes at least its two type bytes
+        if width as usize * height as usize > input.bytes.len() / 2 {
+            return Err("the snapshot ends early".to_string());
+        }
+        // decode everything before touching the grid, so a broken snapshot changes nothing
+        let mut cells = Vec::with_capacity((width * height) as usize);
+        for _ in 0..width * height {
+            let (state, fields, places, defaults) = types
+                .get(input.u16()? as usize)
+                .ok_or("a cell has an unknown type")?;
+            let mut data = defaults.clone();
+            for ((_, tag_of), place) in fields.iter().zip(places) {
+                let value = match tag_of {
+                    0 => Field::Int(input.u64()? as i64),
+                    1 => Field::Float(f64::from_bits(input.u64()?)),
+                    2 => Field::Bool(input.u8()? != 0),
+                    other => return Err(format!("unknown field type {}", other)),
+                };
+                if let Some(i) = place {
+                    data[*i] = value;
+                }
+            }
+            cells.push((*state, data));
+        }
+        if !input.bytes.is_empty() {
+            return Err("trailing bytes after the cells".to_string());
+        }
+
+        grid.resize(width, height);
+        for (i, (state, data)) in cells.into_iter().enumerate() {
+            let (x, y) = (i as i32 % width, i as i32 / width);
+            grid.paint_cell(x, y, state, data).unwrap();
+        }
+        let mut rng = self.rng();
+        *rng = ChaCha8Rng::from_seed(seed);
+        rng.set_stream(stream);
+        rng.set_word_pos(word_pos);
+        Ok(())
+    }
+}
+
+/// The type tag of a field in a snapshot.
+fn tag(value: &Field) -> u8 {
+    match value {
+        Field::Int(_) => 0,
+        Field::Float(_) => 1,
+        Field::Bool(_) => 2,
+    }
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u16).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// The unread rest of a snapshot.
+struct Input<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < len {
+            return Err("the snapshot ends early".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    fn str(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "a name is not UTF-8".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::grid::{Color, Grid};
+    use crate::neighborhood::Neighborhood;
+
+    /// A 3x2 grid with the named types, sand has an integer and a boolean field and water a float one.
+    fn world(types: &[&str]) -> SharedGrid {
+        let (mut grid, _) = Grid::new(3, 2);
+        for &name in types {
+            let fields = match name {
+                "sand" => vec![("heat".to_string(), Field::Int(0)), ("wet".to_string(), Field::Bool(false))],
+                "water" => vec![("temperature".to_string(), Field::Float(20.0))],
+                _ => Vec::new(),
+            };
+            let color = Color { r: 255, g: 255, b: 255, a: 255 };
+            grid.add_state(color, None, 1, name.to_string(), fields, Neighborhood::default());
+        }
+        SharedGrid::new(grid, 0)
+    }
+
+    /// Every cell by the name of its type, with its fields.
+    fn cells(shared: &SharedGrid) -> Vec<(String, Vec<Field>)> {
+        let grid = shared.lock();
+        grid.iter_region((0, 0), (grid.width, grid.height))
+            .map(|(_, state, data)| (grid.cell_prescriptors[state as usize].name.clone(), data.to_vec()))
+            .collect()
+    }
+
+    fn saved() -> SharedGrid {
+        let shared = world(&["sand", "water"]);
+        {
+            let mut grid = shared.lock();
+            let (sand, water) = (grid.state_by_name("sand").unwrap(), grid.state_by_name("water").unwrap());
+            grid.paint_cell(0, 0, sand, vec![Field::Int(-7), Field::Bool(true)]).unwrap();
+            grid.paint_cell(2, 1, water, vec![Field::Float(99.5)]).unwrap();
+            grid.paint(1, 1, sand).unwrap();
+        }
+        shared.rng().gen::<u64>();
+        shared
+    }
+
+    #[test]
+    fn round_trip() {
+        let shared = saved();
+        let loaded = world(&["sand", "water"]);
+        loaded.from_snapshot(&shared.to_snapshot()).unwrap();
+        assert_eq!(cells(&loaded), cells(&shared));
+        assert_eq!(loaded.rng().gen::<u64>(), shared.rng().gen::<u64>());
+    }
+
+    #[test]
+    fn types_are_matched_by_name() {
+        let shared = saved();
+        let loaded = world(&["rock", "water", "sand"]);
+        loaded.from_snapshot(&shared.to_snapshot()).unwrap();
+        assert_eq!(cells(&loaded), cells(&shared));
+        assert_eq!(loaded.lock().state_at(0, 0), Some(3));
+    }
+
+    #[test]
+    fn missing_type_is_rejected() {
+        let loaded = world(&["sand"]);
+        assert!(loaded.from_snapshot(&saved().to_snapshot()).is_err());
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        let bytes = saved().to_snapshot();
+        let loaded = world(&["sand", "water"]);
+        for len in [0, 4, 12, 40, bytes.len() - 1] {
+            assert!(loaded.from_snapshot(&bytes[..len]).is_err(), "{} bytes", len);
+        }
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert!(loaded.from_snapshot(&extra).is_err());
+        // a failed load leaves the world as it was
+        assert!(cells(&loaded).iter().all(|(name, _)| name == "air"));
+    }
+}
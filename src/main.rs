@@ -1,209 +1,466 @@
 // cstring
-use std::{ffi::{CStr, CString}, time::Instant};
+use std::ffi::CString;
+use std::path::Path;
+use std::time::SystemTime;
 
-use grid::Grid;
-use rand::Rng;
+use automatom_core::cli::{self, Args, Command};
+use automatom_core::grid::{self, Grid, StateId};
+use automatom_core::pattern::Pattern;
+use automatom_core::reader::{read_grid, reload_grid};
+use automatom_core::record::Recorder;
+use automatom_core::scheduler::Scheduler;
 use raylib::{ffi::Rectangle, prelude::*};
-use reader::read_grid;
-use rlua::{UserData, LightUserData};
 
-mod grid;
-mod reader;
+/// Where the grid is drawn: a 450x450 area right of the controls.
+const VIEW: (i32, i32, i32, i32) = (175, 0, 450, 450);
 
-const WIDTH: i32 = 150;
+/// Fits a grid of the given size into `VIEW`, keeping the cells square.
+fn grid_view(width: i32, height: i32) -> (i32, i32, i32, i32) {
+    let (x, y, view_width, view_height) = VIEW;
+    let scale = (view_width as f32 / width as f32).min(view_height as f32 / height as f32);
+    let (w, h) = ((width as f32 * scale) as i32, (height as f32 * scale) as i32);
+    (x + (view_width - w) / 2, y + (view_height - h) / 2, w, h)
+}
+
+/// Converts a cell color to a raylib one.
+fn color(color: grid::Color) -> Color {
+    Color::new(color.r, color.g, color.b, color.a)
+}
+
+/// Draws the grid into the given area, empty cells are left black.
+fn draw_grid(
+    grid: &Grid,
+    d: &mut RaylibDrawHandle,
+    (x, y): (i32, i32),
+    (width, height): (i32, i32),
+) {
+    d.draw_rectangle(x, y, width, height, Color::BLACK);
+
+    let width_ratio = width as f64 / grid.width as f64;
+    let height_ratio = height as f64 / grid.height as f64;
+
+    for ((cx, cy), state, _) in grid.iter_region((0, 0), (grid.width, grid.height)) {
+        if state == 0 {
+            continue;
+        }
+
+        // snap both edges to pixels so cells neither overlap nor leave gaps,
+        // and keep at least one pixel when the grid is larger than the view
+        let cell_x = x + (cx as f64 * width_ratio) as i32;
+        let cell_y = y + (cy as f64 * height_ratio) as i32;
+        let cell_width = (x + ((cx + 1) as f64 * width_ratio) as i32 - cell_x).max(1);
+        let cell_height = (y + ((cy + 1) as f64 * height_ratio) as i32 - cell_y).max(1);
+
+        let me = &grid.cell_prescriptors[state as usize];
+        d.draw_rectangle(cell_x, cell_y, cell_width, cell_height, color(me.color));
+    }
+}
+
+/// Draws errors over the bottom of the grid view, wrapped to its width.
+fn draw_errors(d: &mut RaylibDrawHandle, errors: &[String]) {
+    let (x, y, width, height) = VIEW;
+    let mut lines: Vec<String> = Vec::new();
+    for line in errors.iter().flat_map(|err| err.lines()) {
+        let mut wrapped = String::new();
+        for word in line.split_whitespace() {
+            if !wrapped.is_empty() && measure_text(&format!("{} {}", wrapped, word), 10) > width - 10 {
+                lines.push(std::mem::take(&mut wrapped));
+            }
+            if !wrapped.is_empty() {
+                wrapped.push(' ');
+            }
+            wrapped.push_str(word);
+        }
+        lines.push(wrapped);
+    }
+    // as many as fit into half of the view
+    lines.truncate((height / 2 / 12) as usize);
+    let top = y + height - lines.len() as i32 * 12 - 10;
+    d.draw_rectangle(x, top, width, y + height - top, Color::new(0, 0, 0, 200));
+    for (i, line) in lines.iter().enumerate() {
+        d.draw_text(line, x + 5, top + 5 + i as i32 * 12, 10, Color::new(255, 110, 110, 255));
+    }
+}
+
+/// When the file was last changed, None if that can't be found out.
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
 
 fn main() {
-    let mut rng = rand::thread_rng();
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(Command::Run(args)) => args,
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if args.headless {
+        if let Err(err) = cli::run_headless(&args) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
     let (mut rl, thread) = raylib::init().size(800, 450).title("automaTom").build();
     let ico = Image::load_image("kamen.png");
     if let Ok(ico) = ico {
         rl.set_window_icon(&ico);
     }
     rl.set_target_fps(60);
-    // read first argument as path to grid
-    use std::env;
-    let mut args = env::args();
-    let (grid, options) = if let Some(path) = args.nth(1) {
-        read_grid(&path)
-    } else {
-        panic!("No file specified")
-    };
+    let (mut lua, mut grid, mut options) = read_grid(&args.path, &args.settings).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1)
+    });
+    for err in &options.errors {
+        eprintln!("{}", err);
+    }
+    // rule file and script errors, shown over the grid until the rule file is reloaded
+    let mut errors = options.errors.clone();
+    let mut last_modified = modified(&args.path);
+
+    let mut scheduler = Scheduler::new(options.mode, &grid.lock());
+    // Save, Load, Record and Export write next to the rule file
+    let beside = |extension: &str| Path::new(&args.path).with_extension(extension).to_string_lossy().into_owned();
+    let snapshot = beside("snapshot");
+    let mut recorder: Option<Recorder> = None;
 
     let mut iterations = 1000;
     let mut running = true;
     let mut auto_adjust = true;
     let mut selected = 0;
     let mut brush_size = 1;
-    /*let mut to_update = Vec::new();
-    for x in 0..WIDTH {
-        for y in 0..WIDTH {
-            to_update.push((x, y));
-        }
-    }*/
+    // the corner where a right drag started and the selected rectangle, in grid cells
+    let mut drag_start: Option<(i32, i32)> = None;
+    let mut selection: Option<((i32, i32), (i32, i32))> = None;
 
     while !rl.window_should_close() {
+        // reload the rule file when it changes, the world carries over
+        let now = modified(&args.path);
+        if now != last_modified {
+            last_modified = now;
+            match reload_grid(&args.path, &grid) {
+                Ok((new_lua, new_grid, new_options)) => {
+                    let generation = scheduler.generation;
+                    scheduler = Scheduler::new(new_options.mode, &new_grid.lock());
+                    scheduler.generation = generation;
+                    // keep the selected type if it is still there
+                    let name = options.table.iter().find(|(_, i)| *i == selected as usize).map(|(name, _)| name);
+                    selected = new_options
+                        .table
+                        .iter()
+                        .find(|(other, _)| Some(other) == name)
+                        .map_or(0, |(_, i)| *i as StateId);
+                    // the colors of the frames would change
+                    recorder = None;
+                    errors = new_options.errors.clone();
+                    (lua, grid, options) = (new_lua, new_grid, new_options);
+                }
+                Err(err) => errors = vec![format!("Reloading {} failed: {}", args.path, err)],
+            }
+            for err in &errors {
+                eprintln!("{}", err);
+            }
+        }
         // get drawing context
         let mut d = rl.begin_drawing(&thread);
         // update
         // draw user input
         // chack if mouse is in grid
-        // get grid userdata from lua
-        grid.context(|ctx| {
-            let globals = ctx.globals();
-            let mut userdata = globals.get::<_, Grid>("grid").unwrap();
-            
-            let mouse_pos = d.get_mouse_position();
-            if mouse_pos.x > 175. && mouse_pos.x < 625. && mouse_pos.y > 0. && mouse_pos.y < 450. {
-                // get mouse position in grid
-                let mouse_pos = (
-                    ((mouse_pos.x - 175.) / 450. * WIDTH as f32) as i32,
-                    ((mouse_pos.y) / 450. * WIDTH as f32) as i32,
-                );
-                // set cell to selected state
-                if d.is_mouse_button_down(raylib::consts::MouseButton::MOUSE_LEFT_BUTTON) {
-                    // change state for cells in brush
-                    for x in mouse_pos.0 - brush_size..mouse_pos.0 + brush_size {
-                        for y in mouse_pos.1 - brush_size..mouse_pos.1 + brush_size {
-                            userdata.change_cell(x, y, selected);
-                        }
+        let (width, height) = {
+            let grid = grid.lock();
+            (grid.width, grid.height)
+        };
+        let (view_x, view_y, view_width, view_height) = grid_view(width, height);
+        let mouse_pos = d.get_mouse_position();
+        if mouse_pos.x > view_x as f32
+            && mouse_pos.x < (view_x + view_width) as f32
+            && mouse_pos.y > view_y as f32
+            && mouse_pos.y < (view_y + view_height) as f32
+        {
+            // get mouse position in grid
+            let mouse_pos = (
+                ((mouse_pos.x - view_x as f32) / view_width as f32 * width as f32) as i32,
+                ((mouse_pos.y - view_y as f32) / view_height as f32 * height as f32) as i32,
+            );
+            // set cell to selected state
+            if d.is_mouse_button_down(raylib::consts::MouseButton::MOUSE_LEFT_BUTTON) {
+                // change state for cells in brush
+                let mut grid = grid.lock();
+                for x in mouse_pos.0 - brush_size..mouse_pos.0 + brush_size {
+                    for y in mouse_pos.1 - brush_size..mouse_pos.1 + brush_size {
+                        let _ = grid.paint(x, y, selected);
                     }
-                    // send back userdata
-                    globals.set("grid", userdata).unwrap();
-                    userdata = globals.get::<_, Grid>("grid").unwrap();
                 }
             }
-            // draw grid
-            if running {
-                for _ in 0..iterations {
-                    /*if to_update.is_empty() {
-                        for x in 0..WIDTH {
-                            for y in 0..WIDTH {
-                                to_update.push((x, y));
-                            }
+            // select a rectangle for the RLE buttons by dragging with the right button
+            if d.is_mouse_button_pressed(raylib::consts::MouseButton::MOUSE_RIGHT_BUTTON) {
+                drag_start = Some(mouse_pos);
+            }
+            if let Some(start) = drag_start {
+                let corner = (start.0.min(mouse_pos.0), start.1.min(mouse_pos.1));
+                let size = ((start.0 - mouse_pos.0).abs() + 1, (start.1 - mouse_pos.1).abs() + 1);
+                selection = Some((corner, size));
+            }
+        }
+        if d.is_mouse_button_released(raylib::consts::MouseButton::MOUSE_RIGHT_BUTTON) {
+            drag_start = None;
+        }
+        // draw grid
+        if running {
+            for _ in 0..iterations {
+                // let the scheduler pick the cell
+                if scheduler.step(&lua, &grid) {
+                    if let Some(rec) = &mut recorder {
+                        if let Err(err) = rec.generation(scheduler.generation, &grid.lock()) {
+                            eprintln!("{}", err);
+                            recorder = None;
                         }
-                    }*/
-                    // randomly select cell from to_update and remove it from the list
-                    let cell = (rng.gen_range(0..WIDTH), rng.gen_range(0..WIDTH));
-                    //let cell = to_update.remove(index);
-                    // set userdata current cell to cell
-                    globals.set("x", cell.0).unwrap();
-                    globals.set("y", cell.1).unwrap();
-                    // get state of the cell to determine script to run
-                    let state = ctx.load("grid:cellState(x, y)").eval::<i32>().unwrap();
-                    // get script to run
-                    let script = &userdata.cell_prescriptors[state as usize].update;
-                    Grid::update(&grid, cell, script);
-                }
-                userdata = globals.get::<_, Grid>("grid").unwrap();
-                if auto_adjust {
-                    iterations += if d.get_fps() < 24 { -20 } else { 20 };
+                    }
                 }
             }
-            d.clear_background(Color::WHITE);
-            userdata.draw(&mut d, (175, 0), (450, 450));
-            // draw iterations slider
-            iterations = d.gui_slider(
-                Rectangle {
-                    x: 10.,
-                    y: 40.,
-                    width: 155.,
-                    height: 20.,
-                },
-                None,
-                None,
-                iterations as f32,
-                0.,
-                10000.,
-            ) as i32;
-            d.draw_text(
-                &format!("Iterations: {}", iterations),
-                12,
-                45,
-                12,
-                Color::BLACK,
-            );
-            // draw run/pause button
-            let text_to_draw = if running { "Pause" } else { "Run" };
-            if d.gui_button(
-                Rectangle {
-                    x: 10.,
-                    y: 70.,
-                    width: 155.,
-                    height: 20.,
-                },
-                Some(CString::new(text_to_draw).unwrap().as_c_str()),
-            ) {
-                running = !running;
+            if auto_adjust {
+                iterations += if d.get_fps() < 24 { -20 } else { 20 };
             }
-            // draw auto adjust button
-            let text_to_draw = if auto_adjust {
-                "Auto Adjust: On"
+        }
+        // a failed script paused its type, the rest keeps running
+        for err in std::mem::take(&mut grid.lock().script_errors) {
+            let err = format!("{}, {} is paused", err, err.name);
+            eprintln!("{}", err);
+            errors.push(err);
+        }
+        d.clear_background(Color::WHITE);
+        draw_grid(&grid.lock(), &mut d, (view_x, view_y), (view_width, view_height));
+        // draw selection
+        if let Some(((x, y), (w, h))) = selection {
+            let cell = (view_width as f32 / width as f32, view_height as f32 / height as f32);
+            d.draw_rectangle_lines(
+                view_x + (x as f32 * cell.0) as i32,
+                view_y + (y as f32 * cell.1) as i32,
+                ((w as f32 * cell.0) as i32).max(1),
+                ((h as f32 * cell.1) as i32).max(1),
+                Color::YELLOW,
+            );
+        }
+        // draw iterations slider
+        iterations = d.gui_slider(
+            Rectangle {
+                x: 10.,
+                y: 40.,
+                width: 155.,
+                height: 20.,
+            },
+            None,
+            None,
+            iterations as f32,
+            0.,
+            10000.,
+        ) as i32;
+        d.draw_text(
+            &format!("Iterations: {}", iterations),
+            12,
+            45,
+            12,
+            Color::BLACK,
+        );
+        // draw run/pause button
+        let text_to_draw = if running { "Pause" } else { "Run" };
+        if d.gui_button(
+            Rectangle {
+                x: 10.,
+                y: 70.,
+                width: 75.,
+                height: 20.,
+            },
+            Some(CString::new(text_to_draw).unwrap().as_c_str()),
+        ) {
+            running = !running;
+        }
+        // draw record button, a GIF of every generation until pressed again
+        let text_to_draw = if recorder.is_some() { "Stop" } else { "Record" };
+        if d.gui_button(
+            Rectangle {
+                x: 90.,
+                y: 70.,
+                width: 75.,
+                height: 20.,
+            },
+            Some(CString::new(text_to_draw).unwrap().as_c_str()),
+        ) {
+            recorder = match recorder {
+                Some(_) => None,
+                None => {
+                    let grid = grid.lock();
+                    let started = Recorder::new(&beside("gif"), &grid, 1, 1)
+                        .and_then(|mut rec| rec.capture(&grid).map(|_| rec));
+                    started.map_err(|err| eprintln!("{}", err)).ok()
+                }
+            };
+        }
+        // draw auto adjust button
+        let text_to_draw = if auto_adjust {
+            "Auto Adjust: On"
+        } else {
+            "Auto Adjust: Off"
+        };
+        if d.gui_button(
+            Rectangle {
+                x: 10.,
+                y: 100.,
+                width: 155.,
+                height: 20.,
+            },
+            Some(CString::new(text_to_draw).unwrap().as_c_str()),
+        ) {
+            auto_adjust = !auto_adjust;
+        }
+        // draw clear button
+        if d.gui_button(
+            Rectangle {
+                x: 10.,
+                y: 130.,
+                width: 155.,
+                height: 20.,
+            },
+            Some(CString::new("Clear").unwrap().as_c_str()),
+        ) {
+            grid.lock().clear();
+            selection = None;
+        }
+        // draw cell type buttons
+        for (name, i) in &options.table {
+            let label = if grid.lock().cell_prescriptors[*i].paused {
+                format!("{} (paused)", name)
             } else {
-                "Auto Adjust: Off"
+                name.clone()
             };
             if d.gui_button(
                 Rectangle {
-                    x: 10.,
-                    y: 100.,
+                    x: (VIEW.0 + VIEW.2 + 10) as f32,
+                    y: 10. + (*i as f32 * 30.),
                     width: 155.,
                     height: 20.,
                 },
-                Some(CString::new(text_to_draw).unwrap().as_c_str()),
+                Some(CString::new(label).unwrap().as_c_str()),
             ) {
-                auto_adjust = !auto_adjust;
+                selected = *i as StateId;
             }
-            // draw clear button
-            if d.gui_button(
-                Rectangle {
-                    x: 10.,
-                    y: 130.,
-                    width: 155.,
-                    height: 20.,
-                },
-                Some(CString::new("Clear").unwrap().as_c_str()),
-            ) {
-                userdata.clear();
-                // send back userdata
-                globals.set("grid", userdata).unwrap();
-                userdata = globals.get::<_, Grid>("grid").unwrap();
+        }
+        // draw brush size slider
+        brush_size = d.gui_slider(
+            Rectangle {
+                x: 10.,
+                y: 160.,
+                width: 155.,
+                height: 20.,
+            },
+            None,
+            None,
+            brush_size as f32,
+            1.,
+            10.,
+        ) as i32;
+        d.draw_text(
+            &format!("Brush Size: {}", brush_size),
+            12,
+            165,
+            12,
+            Color::BLACK,
+        );
+        d.draw_text(
+            &format!("Generation: {}", scheduler.generation),
+            12,
+            190,
+            12,
+            Color::BLACK,
+        );
+        // draw save and load buttons
+        if d.gui_button(
+            Rectangle {
+                x: 10.,
+                y: 210.,
+                width: 75.,
+                height: 20.,
+            },
+            Some(CString::new("Save").unwrap().as_c_str()),
+        ) {
+            if let Err(err) = grid.save(&snapshot) {
+                eprintln!("{}", err);
             }
-            // draw cell type buttons
-            for (i, cell) in userdata.cell_prescriptors.iter().enumerate() {
-                if d.gui_button(
-                    Rectangle {
-                        x: 175. + 450. + 10.,
-                        y: 10. + (i as f32 * 30.),
-                        width: 155.,
-                        height: 20.,
-                    },
-                    Some(CString::new(cell.name.to_string()).unwrap().as_c_str()),
-                ) {
-                    selected = i as u8;
+        }
+        if d.gui_button(
+            Rectangle {
+                x: 90.,
+                y: 210.,
+                width: 75.,
+                height: 20.,
+            },
+            Some(CString::new("Load").unwrap().as_c_str()),
+        ) {
+            match grid.load(&snapshot) {
+                Ok(()) => {
+                    scheduler = Scheduler::new(options.mode, &grid.lock());
+                    selection = None;
+                    // the frames would change size
+                    recorder = None;
                 }
+                Err(err) => eprintln!("{}", err),
             }
-            // draw brush size slider
-            brush_size = d.gui_slider(
-                Rectangle {
-                    x: 10.,
-                    y: 160.,
-                    width: 155.,
-                    height: 20.,
-                },
-                None,
-                None,
-                brush_size as f32,
-                1.,
-                10.,
-            ) as i32;
-            d.draw_text(
-                &format!("Brush Size: {}", brush_size),
-                12,
-                165,
-                12,
-                Color::BLACK,
-            );
-            // draw fps
-            d.draw_fps(12, 12);
-        });
+        }
+        // draw export button
+        if d.gui_button(
+            Rectangle {
+                x: 10.,
+                y: 240.,
+                width: 155.,
+                height: 20.,
+            },
+            Some(CString::new("Export PNG").unwrap().as_c_str()),
+        ) {
+            if let Err(err) = grid.lock().to_image(1).write_png(&beside("png")) {
+                eprintln!("{}", err);
+            }
+        }
+        // draw pattern buttons, for the selection or else the whole grid, with type ids as states
+        if d.gui_button(
+            Rectangle {
+                x: 10.,
+                y: 270.,
+                width: 75.,
+                height: 20.,
+            },
+            Some(CString::new("Export RLE").unwrap().as_c_str()),
+        ) {
+            let grid = grid.lock();
+            let (corner, size) = selection.unwrap_or(((0, 0), (grid.width, grid.height)));
+            if let Err(err) = grid.pattern(corner, size, &[]).write(&beside("rle")) {
+                eprintln!("{}", err);
+            }
+        }
+        if d.gui_button(
+            Rectangle {
+                x: 90.,
+                y: 270.,
+                width: 75.,
+                height: 20.,
+            },
+            Some(CString::new("Import RLE").unwrap().as_c_str()),
+        ) {
+            let corner = selection.map_or((0, 0), |(corner, _)| corner);
+            let placed = Pattern::read(&beside("rle"))
+                .and_then(|pattern| grid.lock().place_pattern(&pattern, corner, &[]));
+            if let Err(err) = placed {
+                eprintln!("{}", err);
+            }
+        }
+        if !errors.is_empty() {
+            draw_errors(&mut d, &errors);
+        }
+        // draw fps
+        d.draw_fps(12, 12);
     }
 }